@@ -0,0 +1,275 @@
+//! Second order IIR filter with the coefficients from the RBJ audio EQ cookbook.
+
+use std::f64::consts::PI;
+
+use nih_plug::prelude::*;
+
+/// Response of a [`Biquad`].
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FilterType {
+    /// Passes the signal unchanged.
+    #[id = "off"]
+    Off,
+    #[id = "high-pass"]
+    #[name = "High-pass"]
+    HighPass,
+    #[id = "low-pass"]
+    #[name = "Low-pass"]
+    LowPass,
+    /// Constant 0 dB peak gain at the center frequency.
+    #[id = "band-pass"]
+    #[name = "Band-pass"]
+    BandPass,
+    #[id = "low-shelf"]
+    #[name = "Low shelf"]
+    LowShelf,
+    #[id = "high-shelf"]
+    #[name = "High shelf"]
+    HighShelf,
+}
+
+/// Filter coefficients, normalized so `a0` is one. They are kept in f64: the crossovers run at up
+/// to eight times the host sample rate, where `cos(omega)` of a low frequency rounds to one in f32
+/// and the filters lose their response.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coefficients {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+impl Coefficients {
+    pub const IDENTITY: Self = Self {
+        b0: 1.0,
+        b1: 0.0,
+        b2: 0.0,
+        a1: 0.0,
+        a2: 0.0,
+    };
+
+    /// Computes the coefficients for `filter_type` at `frequency` Hz. `gain_db` is only used by
+    /// the shelves.
+    pub fn new(
+        filter_type: FilterType,
+        sample_rate: f32,
+        frequency: f32,
+        q: f32,
+        gain_db: f32,
+    ) -> Self {
+        let (sample_rate, q) = (sample_rate as f64, q as f64);
+        let frequency = (frequency as f64).clamp(1.0, sample_rate * 0.49);
+        let omega = 2.0 * PI * frequency / sample_rate;
+        let (sin, cos) = omega.sin_cos();
+        let alpha = sin / (2.0 * q);
+        let a = 10.0f64.powf(gain_db as f64 / 40.0);
+        let shelf_alpha = 2.0 * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match filter_type {
+            FilterType::Off => return Self::IDENTITY,
+            FilterType::HighPass => (
+                (1.0 + cos) / 2.0,
+                -(1.0 + cos),
+                (1.0 + cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            FilterType::LowPass => (
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            FilterType::BandPass => (alpha, 0.0, -alpha, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterType::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos + shelf_alpha),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - shelf_alpha),
+                (a + 1.0) + (a - 1.0) * cos + shelf_alpha,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - shelf_alpha,
+            ),
+            FilterType::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos + shelf_alpha),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - shelf_alpha),
+                (a + 1.0) - (a - 1.0) * cos + shelf_alpha,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - shelf_alpha,
+            ),
+        };
+
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+
+    /// Computes the coefficients of a second order all-pass whose phase passes through -180
+    /// degrees at `frequency` Hz.
+    pub fn all_pass(sample_rate: f32, frequency: f32, q: f32) -> Self {
+        let (sample_rate, q) = (sample_rate as f64, q as f64);
+        let frequency = (frequency as f64).clamp(1.0, sample_rate * 0.49);
+        let omega = 2.0 * PI * frequency / sample_rate;
+        let (sin, cos) = omega.sin_cos();
+        let alpha = sin / (2.0 * q);
+        let a0 = 1.0 + alpha;
+
+        Self {
+            b0: (1.0 - alpha) / a0,
+            b1: -2.0 * cos / a0,
+            b2: 1.0,
+            a1: -2.0 * cos / a0,
+            a2: (1.0 - alpha) / a0,
+        }
+    }
+}
+
+/// Biquad in transposed direct form II. The state is kept in f64 like the coefficients: for a low
+/// frequency at a high sample rate its terms nearly cancel, and f32 would lose the difference.
+pub struct Biquad {
+    coefficients: Coefficients,
+    s1: f64,
+    s2: f64,
+}
+
+impl Biquad {
+    pub fn new(coefficients: Coefficients) -> Self {
+        Self {
+            coefficients,
+            s1: 0.0,
+            s2: 0.0,
+        }
+    }
+
+    /// Replaces the coefficients while keeping the state, so the filter can be swept.
+    pub fn set_coefficients(&mut self, coefficients: Coefficients) {
+        self.coefficients = coefficients;
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        let c = &self.coefficients;
+        let sample = sample as f64;
+        let output = c.b0 * sample + self.s1;
+
+        let s1 = c.b1 * sample - c.a1 * output + self.s2;
+        let s2 = c.b2 * sample - c.a2 * output;
+        (self.s1, self.s2) = flush(s1, s2);
+
+        output as f32
+    }
+
+    pub fn reset(&mut self) {
+        self.s1 = 0.0;
+        self.s2 = 0.0;
+    }
+}
+
+/// The state `(s1, s2)`, or zeros once both are too small to show in the f32 output or when
+/// either is infinite or NaN. Like [`crate::compressor::flush`] for the f32 state, this ends a
+/// decaying filter instead of letting it run into subnormals and stops a NaN or infinity from
+/// staying in it. The two are flushed together, at low frequencies they nearly cancel and
+/// zeroing only one would excite the filter again.
+fn flush(s1: f64, s2: f64) -> (f64, f64) {
+    let smallest = f32::MIN_POSITIVE as f64;
+
+    if (s1.abs() >= smallest || s2.abs() >= smallest) && s1.is_finite() && s2.is_finite() {
+        (s1, s2)
+    } else {
+        (0.0, 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gain in dB of a filter at 1 kHz with a Q of 0.7071 and a 12 dB shelf, measured with a sine
+    /// at `frequency` Hz once the filter settled.
+    fn response_db(filter_type: FilterType, frequency: f32) -> f32 {
+        let sample_rate = 48000.0;
+        let mut filter = Biquad::new(Coefficients::new(
+            filter_type,
+            sample_rate,
+            1000.0,
+            std::f32::consts::FRAC_1_SQRT_2,
+            12.0,
+        ));
+
+        // One second of a sine is a whole number of periods at every tested frequency.
+        let sine = |n: usize| {
+            (2.0 * std::f32::consts::PI * frequency * (n % 48000) as f32 / sample_rate).sin()
+        };
+        for n in 0..48000 {
+            filter.process(sine(n));
+        }
+        let energy: f64 = (0..48000)
+            .map(|n| (filter.process(sine(n)) as f64).powi(2))
+            .sum();
+
+        util::gain_to_db((2.0 * energy / 48000.0).sqrt() as f32)
+    }
+
+    #[test]
+    fn filters_have_their_cookbook_response() {
+        // Filter type, frequency and the range the gain in dB has to be in.
+        let cases = [
+            (FilterType::Off, 100.0, -0.01, 0.01),
+            (FilterType::HighPass, 1000.0, -3.06, -2.96),
+            (FilterType::HighPass, 10.0, f32::NEG_INFINITY, -75.0),
+            (FilterType::HighPass, 10000.0, -0.05, 0.05),
+            (FilterType::LowPass, 1000.0, -3.06, -2.96),
+            (FilterType::LowPass, 20000.0, f32::NEG_INFINITY, -55.0),
+            (FilterType::LowPass, 10.0, -0.05, 0.05),
+            (FilterType::BandPass, 1000.0, -0.05, 0.05),
+            (FilterType::BandPass, 10.0, f32::NEG_INFINITY, -35.0),
+            (FilterType::BandPass, 20000.0, f32::NEG_INFINITY, -30.0),
+            (FilterType::LowShelf, 1000.0, 5.95, 6.05),
+            (FilterType::LowShelf, 10.0, 11.95, 12.05),
+            (FilterType::LowShelf, 20000.0, -0.05, 0.05),
+            (FilterType::HighShelf, 1000.0, 5.95, 6.05),
+            (FilterType::HighShelf, 20000.0, 11.95, 12.05),
+            (FilterType::HighShelf, 10.0, -0.05, 0.05),
+        ];
+
+        for (filter_type, frequency, min_db, max_db) in cases {
+            let db = response_db(filter_type, frequency);
+            assert!(
+                (min_db..=max_db).contains(&db),
+                "{filter_type:?} at {frequency} Hz: {db} dB"
+            );
+        }
+    }
+
+    #[test]
+    fn state_is_flushed() {
+        let mut filter = Biquad::new(Coefficients::new(
+            FilterType::LowPass,
+            48000.0,
+            20.0,
+            std::f32::consts::FRAC_1_SQRT_2,
+            0.0,
+        ));
+
+        // A NaN or infinity does not stay in the state.
+        for input in [f32::NAN, f32::INFINITY] {
+            filter.process(input);
+            filter.process(0.0);
+            assert_eq!(filter.process(0.0), 0.0, "{input}");
+        }
+
+        // A decaying impulse response ends at zero instead of running through the subnormals.
+        filter.process(1.0);
+        for _ in 0..48000 * 10 {
+            filter.process(0.0);
+        }
+        assert_eq!((filter.s1, filter.s2), (0.0, 0.0));
+    }
+}
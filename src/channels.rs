@@ -0,0 +1,201 @@
+//! Routing between channels around the per-channel [`crate::compressor::Algo`]s.
+
+use nih_plug::prelude::*;
+
+use crate::compressor::{self, DynamicsMode, Prepared};
+
+/// Highest channel count of the supported audio layouts.
+pub const MAX_CHANNELS: usize = 2;
+
+/// How the detector levels of linked channels are combined.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LinkMode {
+    /// The loudest channel drives all channels.
+    #[id = "max"]
+    Max,
+    /// The mean level of all channels drives all channels.
+    #[id = "average"]
+    Average,
+}
+
+/// Gains that link the gain reduction of the channels, applied with
+/// [`crate::compressor::Algo::apply_link_gain`] on top of what every channel did with its own
+/// envelope. Each channel gets the ratio between the clean gain at the combined envelope of all
+/// channels and the clean gain at its own envelope, blended by `link`. With `link` at 0 the
+/// channels stay independent, at 1 the clean architecture gives all channels the same gain. The
+/// limiter only turns its gain further down, so the ceiling holds with any link mode.
+pub fn link_gains(
+    envelopes: &[f32],
+    c: &Prepared,
+    link: f32,
+    mode: LinkMode,
+) -> [f32; MAX_CHANNELS] {
+    let mut gains = [1.0; MAX_CHANNELS];
+    if envelopes.len() < 2 || link <= 0.0 {
+        return gains;
+    }
+
+    let combined = match mode {
+        LinkMode::Max => envelopes.iter().copied().fold(0.0, f32::max),
+        LinkMode::Average => envelopes.iter().sum::<f32>() / envelopes.len() as f32,
+    };
+    let combined_gain = compressor::clean_gain(combined, c);
+
+    for (gain, envelope) in gains.iter_mut().zip(envelopes) {
+        let own_gain = compressor::clean_gain(*envelope, c);
+        let mut ratio = if own_gain > 0.0 {
+            combined_gain / own_gain
+        } else {
+            1.0
+        };
+        if c.p.mode == DynamicsMode::Limiter {
+            ratio = ratio.min(1.0);
+        }
+
+        *gain = 1.0 + (ratio - 1.0) * link;
+    }
+
+    gains
+}
+
+/// Which channels are compressed, and whether that happens on left/right or on mid/side.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ChannelMode {
+    /// Left and right are compressed.
+    #[id = "stereo"]
+    Stereo,
+    /// Only the mid channel is compressed, the side channel passes through.
+    #[id = "mid"]
+    #[name = "Mid only"]
+    Mid,
+    /// Only the side channel is compressed, the mid channel passes through.
+    #[id = "side"]
+    #[name = "Side only"]
+    Side,
+    /// Mid and side are both compressed, the side channel with its own settings.
+    #[id = "mid-side"]
+    #[name = "M/S"]
+    MidSide,
+}
+
+impl ChannelMode {
+    pub fn is_mid_side(&self) -> bool {
+        *self != ChannelMode::Stereo
+    }
+
+    /// Whether channel `index` of the frame, after encoding to mid/side if needed, is compressed.
+    pub fn is_compressed(&self, index: usize) -> bool {
+        match self {
+            ChannelMode::Stereo | ChannelMode::MidSide => true,
+            ChannelMode::Mid => index == 0,
+            ChannelMode::Side => index == 1,
+        }
+    }
+}
+
+/// Turns a left/right frame into a mid/side frame.
+pub fn encode_mid_side(frame: &mut [f32; MAX_CHANNELS]) {
+    let [left, right] = *frame;
+    *frame = [(left + right) / 2.0, (left - right) / 2.0];
+}
+
+/// Turns a mid/side frame back into a left/right frame.
+pub fn decode_mid_side(frame: &mut [f32; MAX_CHANNELS]) {
+    let [mid, side] = *frame;
+    *frame = [mid + side, mid - side];
+}
+
+/// Signal the detectors listen to.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SidechainSource {
+    /// The main input.
+    #[id = "internal"]
+    Internal,
+    /// The sidechain input. Hosts pass silence when nothing is routed to it, which leaves the
+    /// signal uncompressed. Only the layouts without a sidechain port fall back to the main input.
+    #[id = "external"]
+    External,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::tests::parameters;
+    use crate::compressor::{Algo, Architecture, RawParameters};
+
+    /// Input and output of one second of a loud left channel and a right channel below the
+    /// threshold, out of phase, compressed with `link`.
+    fn run_linked(p: RawParameters, link: f32) -> Vec<([f32; MAX_CHANNELS], [f32; MAX_CHANNELS])> {
+        let sample_rate = 48000.0;
+        let mut algos = [Algo::new(sample_rate), Algo::new(sample_rate)];
+
+        (0..sample_rate as usize)
+            .map(|n| {
+                let phase = 2.0 * std::f32::consts::PI * 100.0 * n as f32 / sample_rate;
+                let input = [0.9 * phase.sin(), 0.05 * (phase + 1.0).sin()];
+
+                let prepared = algos.each_mut().map(|algo| algo.prepare(&p));
+                let mut output = input;
+                for ((sample, algo), c) in output.iter_mut().zip(&mut algos).zip(&prepared) {
+                    let level = algo.detect(*sample, c);
+                    algo.process_with_level(sample, level, c, None).unwrap();
+                }
+
+                let envelopes = algos.each_ref().map(Algo::get_envelope);
+                let gains = link_gains(&envelopes, &prepared[0], link, LinkMode::Max);
+                for ((sample, algo), gain) in output.iter_mut().zip(&algos).zip(gains) {
+                    algo.apply_link_gain(sample, gain, &prepared[0]);
+                }
+
+                (input, output)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fully_linked_channels_get_the_same_gain() {
+        let p = RawParameters {
+            attack: 0.001,
+            ..parameters()
+        };
+
+        for (n, (input, output)) in run_linked(p, 1.0).into_iter().enumerate() {
+            if input.iter().all(|sample| sample.abs() > 1e-3) {
+                let [left, right] = [0, 1].map(|index| output[index] / input[index]);
+                assert!(
+                    (left - right).abs() < 1e-4,
+                    "sample {n}: {left} and {right}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn partial_link_moves_continuously_from_unlinked() {
+        // Linking keeps the waveshaping of the character architecture and a parallel mix.
+        let p = RawParameters {
+            architecture: Architecture::Character,
+            attack: 0.001,
+            mix: 0.7,
+            ..parameters()
+        };
+        let unlinked = run_linked(p, 0.0);
+        let distance = |link: f32| {
+            run_linked(p, link)
+                .iter()
+                .zip(&unlinked)
+                .flat_map(|((_, a), (_, b))| a.iter().zip(b).map(|(a, b)| (a - b).abs()))
+                .fold(0.0, f32::max)
+        };
+
+        let full = distance(1.0);
+        assert!(full > 0.01, "full link: {full}");
+        for link in [0.01, 0.1, 0.5] {
+            let partial = distance(link);
+            assert!(
+                partial <= full * link * 1.01,
+                "link {link}: {partial}, full link: {full}"
+            );
+        }
+    }
+}
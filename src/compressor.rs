@@ -1,39 +1,409 @@
 use llad::SampleLogger;
+use nih_plug::prelude::*;
+
+use crate::biquad::{Biquad, Coefficients, FilterType};
+use crate::delay::DelayLine;
+use crate::detector::{
+    true_peak_margin, Detector, DetectorMode, PeakDetector, RmsDetector, TruePeakDetector,
+    TRUE_PEAK_LATENCY,
+};
+use crate::limiter::Limiter;
+use crate::model::{AnalogModel, Fet, Model, Opto, Vca};
+use crate::oversampling::MAX_OVERSAMPLING;
+
+/// Longest lookahead that can be set, in seconds. The delay line is sized for this.
+pub const MAX_LOOKAHEAD: f32 = 0.01;
+
+/// Samples [`Algo::process_block`] computes the gain for at once.
+const BLOCK_SIZE: usize = 64;
+
+/// Longest hold that can be set, in seconds.
+pub const MAX_HOLD: f32 = 0.5;
+
+/// Level that comes out at the same level when automatic makeup gain is enabled.
+const MAKEUP_REFERENCE: f32 = 1.0;
+
+/// Time constant of the smoothing on the automatic makeup gain, in seconds.
+const MAKEUP_SMOOTHING: f32 = 0.05;
+
+/// Range in dB the envelope falls during one release time with [`Ballistics::LogRelease`].
+const LOG_RELEASE_RANGE_DB: f32 = 60.0;
+
+/// How much slower than the set release the sustained stage of [`Ballistics::AutoRelease`]
+/// charges and releases.
+const AUTO_RELEASE_SLOW_FACTOR: f32 = 10.0;
+
+/// Curve the envelope follows while moving towards the detected level.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Ballistics {
+    /// Constant slope: the envelope travels from 0 to full scale in the attack or release time.
+    #[id = "linear"]
+    Linear,
+    /// One-pole RC curve: the envelope covers 63% of the distance to the detected level in the
+    /// attack or release time.
+    #[id = "exponential"]
+    Exponential,
+    /// One-pole attack, the release falls at a constant rate of [`LOG_RELEASE_RANGE_DB`] per
+    /// release time, i.e. linear in dB.
+    #[id = "log-release"]
+    #[name = "Log release"]
+    LogRelease,
+    /// Program-dependent release with two time constants. A fast one-pole stage follows the
+    /// transients with the set attack and release, while a slow stage charges up during sustained
+    /// compression and holds the envelope up so it releases slowly afterwards.
+    #[id = "auto-release"]
+    #[name = "Auto release"]
+    AutoRelease,
+}
+
+/// What happens to the signal relative to the threshold.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DynamicsMode {
+    /// Turns the signal above the threshold down by the ratio.
+    #[id = "compressor"]
+    Compressor,
+    /// Raises the signal below the threshold by the ratio, at most by the range. The signal above
+    /// the threshold passes unchanged.
+    #[id = "upward-compressor"]
+    #[name = "Upward compressor"]
+    UpwardCompressor,
+    /// Turns the signal below the threshold further down by the ratio, at most by the range.
+    #[id = "expander"]
+    Expander,
+    /// Turns the signal down by the range while the envelope is below the threshold.
+    #[id = "gate"]
+    Gate,
+    /// Keeps the true peak of the output below the ceiling. Reads the input with the true-peak
+    /// detector and reaches the needed gain within the lookahead, so nothing gets through.
+    #[id = "limiter"]
+    Limiter,
+}
+
+/// How the compression is applied to the signal.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Architecture {
+    /// Bends the waveform around the threshold sample by sample. Acts partly as a waveshaper and
+    /// adds harmonics, which is how this compressor has always sounded.
+    #[id = "character"]
+    Character,
+    /// Evaluates the static curve on the envelope and multiplies the sample with the resulting
+    /// gain, so no harmonics are added beyond what the envelope movement causes.
+    #[id = "clean"]
+    Clean,
+}
+
+/// Shape of the transition between uncompressed and compressed around the threshold.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Knee {
+    /// Sigmoid on the linear distance to the threshold, scaled by the steepness. Its width in dB
+    /// changes with the threshold.
+    #[id = "steepness"]
+    Steepness,
+    /// Quadratic knee in the dB domain with a fixed width in dB.
+    #[id = "width"]
+    Width,
+}
+
+/// Which signal the detector listens to.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Topology {
+    /// The detector reads the input, the static curve is followed exactly.
+    #[id = "feed-forward"]
+    #[name = "Feed-forward"]
+    FeedForward,
+    /// The detector reads the previous compressed output, like many classic units. The effective
+    /// ratio is softer than the set ratio because the detector sees already reduced peaks.
+    #[id = "feedback"]
+    Feedback,
+}
 
 pub struct Algo {
     envelope: f32,
+    /// Sustained stage of [`Ballistics::AutoRelease`], the envelope never falls below it.
+    slow_envelope: f32,
+    /// Samples the envelope still stays at its peak before the release starts.
+    hold_remaining: f32,
+    /// Whether the envelope was held on the last sample.
+    holding: bool,
+    /// Whether the gate is open, it only closes once the envelope falls through the hysteresis.
+    gate_open: bool,
+    /// Gain of the gate, smoothed so opening and closing does not click.
+    gate_gain: f32,
+    limiter: Limiter,
+    vca: Vca,
+    fet: Fet,
+    opto: Opto,
     sample_rate: f32,
+    /// Compressed output of the previous sample before the gain, read by the feedback topology.
+    previous_output: f32,
+    /// Delayed input of the last sample [`Algo::process_with_level`] compressed.
+    dry: f32,
+    /// Output of the limiter for the last sample before the fractional delay.
+    limited: f32,
+    /// Input or sidechain signal of the last sample after the sidechain filter, what Sidechain
+    /// Listen outputs.
+    key: f32,
+    key_filter: Biquad,
+    /// Sidechain filter for the previous output the feedback topology detects.
+    feedback_filter: Biquad,
+    /// Filter type, frequency, Q and gain the key filter coefficients were computed for.
+    key_filter_settings: (FilterType, f32, f32, f32),
+    /// Values derived from the last parameters, `None` until the first sample.
+    prepared: Option<Prepared>,
+    /// Delays the audio path by the lookahead while the detector reads the undelayed signal.
+    lookahead: DelayLine,
+    /// Smoothed automatic makeup gain, so changing the curve does not make the level jump.
+    makeup: f32,
+    makeup_coefficient: f32,
+    peak: PeakDetector,
+    rms: RmsDetector,
+    true_peak: TruePeakDetector,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct RawParameters {
     pub threshold: f32,
     pub ratio: f32,
     pub steepness: f32,
+    pub knee: Knee,
+    pub knee_width: f32,
+    pub mode: DynamicsMode,
+    pub model: AnalogModel,
+    /// Most the expander and gate turn the signal down, or the upward compressor turns it up, in
+    /// dB.
+    pub range: f32,
+    /// Distance in dB the envelope has to fall below the threshold to close the gate.
+    pub hysteresis: f32,
+    /// Highest true peak the limiter lets through, as gain.
+    pub ceiling: f32,
     pub attack: f32,
     pub release: f32,
+    pub hold: f32,
     pub gain: f32,
+    pub ballistics: Ballistics,
+    pub architecture: Architecture,
+    pub topology: Topology,
+    pub detector: DetectorMode,
+    pub rms_window: f32,
+    pub sidechain_filter: FilterType,
+    pub sidechain_frequency: f32,
+    pub sidechain_q: f32,
+    pub sidechain_gain: f32,
+    pub lookahead: f32,
+    pub auto_makeup: bool,
+    /// Amount of compressed signal in the output, the rest is the dry signal.
+    pub mix: f32,
+}
+
+/// Values derived from [`RawParameters`] that stay the same as long as the parameters do, so
+/// they are not recomputed on every sample. [`Algo`] prepares them again only when the
+/// parameters changed.
+#[derive(Debug, Clone, Copy)]
+pub struct Prepared {
+    /// Parameters the values were derived from, after the analog model changed them.
+    pub p: RawParameters,
+    /// Ballistics of the attack and release as set, before the model scales them.
+    timing: Timing,
+    hold_samples: f32,
+    threshold_db: f32,
+    /// Level the knee of [`Knee::Width`] starts at, half the knee width below the threshold.
+    knee_start: f32,
+    /// Maps the envelope from the threshold to full scale onto [0, 1].
+    envelope_scaler: f32,
+    /// Gain the expander and the gate do not go below.
+    range_gain: f32,
+    /// Gain the upward compressor does not go above.
+    boost_gain: f32,
+    /// Envelope the gate closes below, the threshold minus the hysteresis.
+    close_threshold: f32,
+    /// Automatic makeup gain, one when it is disabled.
+    makeup: f32,
+    /// Samples the limiter holds its gain over.
+    limiter_window: usize,
+    /// Part of the lookahead below a whole sample, which the limiter delays its output by.
+    limiter_fraction: f32,
+    /// Peak the limiter aims at, the ceiling less what the true-peak detector can miss.
+    limiter_ceiling: f32,
+    /// Samples the audio path is delayed by.
+    delay: f32,
+}
+
+impl Prepared {
+    pub fn new(p: &RawParameters, sample_rate: f32) -> Self {
+        let limiter_window = limiter_window(p, sample_rate);
+
+        let mut prepared = Self {
+            p: *p,
+            timing: Timing::new(p.attack * sample_rate, p.release * sample_rate),
+            hold_samples: p.hold * sample_rate,
+            threshold_db: util::gain_to_db(p.threshold),
+            knee_start: p.threshold * util::db_to_gain(-p.knee_width / 2.0),
+            // A threshold at full scale leaves no room above it, the ratio then applies at once.
+            envelope_scaler: 1.0 / (1.0 - p.threshold).max(f32::EPSILON),
+            range_gain: util::db_to_gain(-p.range),
+            boost_gain: util::db_to_gain(p.range),
+            close_threshold: p.threshold * util::db_to_gain(-p.hysteresis),
+            makeup: 1.0,
+            limiter_window,
+            limiter_fraction: (p.lookahead * sample_rate).fract(),
+            limiter_ceiling: p.ceiling * true_peak_margin(),
+            delay: delay(p, sample_rate),
+        };
+
+        if p.auto_makeup {
+            prepared.makeup = makeup_gain(&prepared);
+        }
+        prepared
+    }
+
+    /// Gain that brings a full scale signal back to full scale after the static curve, whether
+    /// or not automatic makeup is enabled.
+    pub fn makeup_gain(&self) -> f32 {
+        makeup_gain(self)
+    }
+}
+
+/// Values derived from the attack and the release times. Kept apart from the rest of
+/// [`Prepared`] because program-dependent models change them on every sample.
+#[derive(Debug, Clone, Copy)]
+struct Timing {
+    /// One-pole coefficients of the attack and the release.
+    attack_coefficient: f32,
+    release_coefficient: f32,
+    /// Steps of [`Ballistics::Linear`], full scale per attack or release time.
+    attack_step: f32,
+    release_step: f32,
+    /// Factor the envelope falls by per sample with [`Ballistics::LogRelease`].
+    log_release_factor: f32,
+    /// One-pole coefficient of the sustained stage of [`Ballistics::AutoRelease`].
+    slow_coefficient: f32,
+}
+
+impl Timing {
+    fn new(attack_samples: f32, release_samples: f32) -> Self {
+        // Zero times move the envelope onto the level at once instead of dividing by zero.
+        let attack_samples = attack_samples.max(f32::MIN_POSITIVE);
+        let release_samples = release_samples.max(f32::MIN_POSITIVE);
+
+        Self {
+            attack_coefficient: one_pole_coefficient(attack_samples),
+            release_coefficient: one_pole_coefficient(release_samples),
+            attack_step: 1.0 / attack_samples,
+            release_step: 1.0 / release_samples,
+            log_release_factor: util::db_to_gain(-LOG_RELEASE_RANGE_DB / release_samples),
+            slow_coefficient: one_pole_coefficient(release_samples * AUTO_RELEASE_SLOW_FACTOR),
+        }
+    }
 }
 
 impl Algo {
-    pub fn new() -> Self {
+    pub fn new(sample_rate: f32) -> Self {
         Self {
             envelope: 0.0,
-            sample_rate: 48000.0,
+            slow_envelope: 0.0,
+            hold_remaining: 0.0,
+            holding: false,
+            gate_open: true,
+            gate_gain: 1.0,
+            limiter: Limiter::new(lookahead_capacity(sample_rate)),
+            vca: Vca,
+            fet: Fet::new(),
+            opto: Opto::new(),
+            sample_rate,
+            previous_output: 0.0,
+            dry: 0.0,
+            limited: 0.0,
+            key: 0.0,
+            key_filter: Biquad::new(Coefficients::IDENTITY),
+            feedback_filter: Biquad::new(Coefficients::IDENTITY),
+            key_filter_settings: (FilterType::Off, 0.0, 0.0, 0.0),
+            prepared: None,
+            lookahead: DelayLine::new(lookahead_capacity(sample_rate)),
+            makeup: 1.0,
+            makeup_coefficient: makeup_coefficient(sample_rate),
+            peak: PeakDetector,
+            rms: RmsDetector::new(sample_rate),
+            true_peak: TruePeakDetector::new(),
         }
     }
 
+    /// Clears the envelope, the sample rate is kept as the host did not change it.
     pub fn reset(&mut self) {
         self.envelope = 0.0;
-        self.sample_rate = 48000.0;
+        self.slow_envelope = 0.0;
+        self.hold_remaining = 0.0;
+        self.holding = false;
+        self.gate_open = true;
+        self.gate_gain = 1.0;
+        self.limiter.reset();
+        self.vca.reset();
+        self.fet.reset();
+        self.opto.reset();
+        self.previous_output = 0.0;
+        self.dry = 0.0;
+        self.limited = 0.0;
+        self.key = 0.0;
+        self.key_filter.reset();
+        self.feedback_filter.reset();
+        self.lookahead.reset();
+        self.makeup = 1.0;
+        self.peak.reset();
+        self.rms.reset();
+        self.true_peak.reset();
+    }
+
+    /// Changes the sample rate. The RMS buffer and the lookahead delay line only grow, so this
+    /// only allocates on the audio thread when the rate is higher than any rate used before.
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        if sample_rate == self.sample_rate {
+            return;
+        }
+
+        self.sample_rate = sample_rate;
+        self.rms.set_sample_rate(sample_rate);
+        self.lookahead
+            .set_max_delay(lookahead_capacity(sample_rate));
+        self.limiter.set_max_window(lookahead_capacity(sample_rate));
+        self.makeup_coefficient = makeup_coefficient(sample_rate);
+        self.key_filter_settings = (FilterType::Off, 0.0, 0.0, 0.0);
+        self.prepared = None;
     }
 
     pub fn process_samples(
         &mut self,
         sample: &mut f32,
         p: RawParameters,
+        logger: Option<&mut SampleLogger>,
+    ) -> Result<(), &'static str> {
+        let c = self.prepare(&p);
+        let level = self.detect(*sample, &c);
+        self.process_with_level(sample, level, &c, logger)
+    }
+
+    /// Compresses `sample` with the envelope following `level`. The level normally comes from
+    /// [`Algo::detect`], but can be combined with the levels of other channels first. `c` comes
+    /// from [`Algo::prepare`] for the same sample.
+    pub fn process_with_level(
+        &mut self,
+        sample: &mut f32,
+        level: f32,
+        c: &Prepared,
         mut logger: Option<&mut SampleLogger>,
     ) -> Result<(), &'static str> {
+        let p = &c.p;
+        let level = flush(level);
+
+        // The gain of the limiter only lines up with whole samples, the rest of the lookahead
+        // delays its output.
+        let delay = if p.mode == DynamicsMode::Limiter {
+            (c.limiter_window - 1 + TRUE_PEAK_LATENCY) as f32
+        } else {
+            c.delay
+        };
+        *sample = self.lookahead.process(flush(*sample), delay);
+        let dry = *sample;
+        self.dry = dry;
+
         if let Some(logger) = &mut logger {
             logger.write("sample", *sample)?;
             logger.write("sample.abs()", (*sample).abs())?;
@@ -42,50 +412,1805 @@ impl Algo {
             logger.write("-threshold", -p.threshold)?;
         }
 
-        let attack_slope = 1.0 / (self.sample_rate * p.attack);
-        let release_slope = 1.0 / (self.sample_rate * p.release);
+        let t = self.timing(c);
+        self.envelope = flush(self.follow(level, c, &t));
 
-        let envelope_scaler = 1.0 / (1.0 - p.threshold);
+        let (envelope, sample_rate) = (self.envelope, self.sample_rate);
+        self.model(p.model).observe(envelope, c, sample_rate);
 
-        let abs_sample = (*sample).abs();
+        let mix = match (p.mode, p.architecture) {
+            (DynamicsMode::Limiter, _) => {
+                // The gain drives the limiter instead of coming after it.
+                let required = (c.limiter_ceiling / (level * p.gain)).min(1.0);
+                let gain = self
+                    .limiter
+                    .process(required, c.limiter_window, t.release_coefficient);
 
-        self.envelope = if abs_sample > self.envelope {
-            (self.envelope + attack_slope).min(abs_sample)
-        } else if abs_sample < self.envelope {
-            (self.envelope - release_slope).max(abs_sample)
-        } else {
-            self.envelope
+                // Between two limited samples, so this cannot go over the ceiling either.
+                let limited = *sample * p.gain * gain;
+                *sample = limited + (self.limited - limited) * c.limiter_fraction;
+                self.limited = limited;
+                gain
+            }
+            (DynamicsMode::Gate, _) => {
+                self.gate_gain = flush(self.follow_gate(c, &t));
+                *sample *= self.gate_gain;
+                self.gate_gain
+            }
+            (DynamicsMode::Compressor, Architecture::Character) => {
+                let mix;
+                (*sample, mix) = character(*sample, self.envelope, c);
+                mix
+            }
+            // The character architecture only has a waveshaper for compression.
+            (_, Architecture::Clean)
+            | (DynamicsMode::UpwardCompressor | DynamicsMode::Expander, _) => {
+                let mix;
+                (*sample, mix) = clean(*sample, self.envelope, c);
+                mix
+            }
         };
 
-        let ratio =
-            1.0 / (((self.envelope - p.threshold) * envelope_scaler) * (p.ratio - 1.0) + 1.0);
+        // The harmonics would push the limited signal over the ceiling again, so the models only
+        // change how fast the limiter releases.
+        if p.mode != DynamicsMode::Limiter {
+            *sample = self.model(p.model).color(*sample, p);
+        }
+        self.previous_output = flush(*sample);
+
+        // Makeup and mix would push the signal over the ceiling again.
+        if p.mode != DynamicsMode::Limiter {
+            self.makeup = c.makeup + self.makeup_coefficient * (self.makeup - c.makeup);
+
+            *sample *= p.gain * self.makeup;
+            *sample = dry + (*sample - dry) * p.mix;
+        }
+
+        // Extreme parameters can still produce a NaN or infinity that would stay in the state
+        // forever, so everything starts over from silence.
+        if !sample.is_finite() {
+            self.reset();
+            *sample = 0.0;
+        }
 
-        let wet = if self.envelope > p.threshold && *sample > p.threshold {
-            p.threshold + (*sample - p.threshold) * ratio
-        } else if -self.envelope < -p.threshold && *sample < -p.threshold {
-            -(p.threshold + (abs_sample - p.threshold) * ratio)
+        if let Some(logger) = &mut logger {
+            logger.write("level", level)?;
+            logger.write("mix", mix)?;
+            logger.write("after", *sample)?;
+        }
+
+        Ok(())
+    }
+
+    /// Turns the compressed part of `sample`, the last output of [`Algo::process_with_level`], by
+    /// `gain` while the dry part of the mix stays as it is. Links the gain reduction to other
+    /// channels, see [`crate::channels::link_gains`].
+    pub fn apply_link_gain(&self, sample: &mut f32, gain: f32, c: &Prepared) {
+        let dry = if c.p.mode == DynamicsMode::Limiter {
+            0.0
         } else {
-            *sample
+            self.dry * (1.0 - c.p.mix)
         };
 
-        let sigmoid = |x: f32| 1.0 / (1.0 + (p.steepness * x).exp());
+        *sample = dry + (*sample - dry) * gain;
+    }
+
+    /// Compresses a whole channel with fixed parameters, the same as calling
+    /// [`Algo::process_samples`] on every sample. The detection and the envelope depend on the
+    /// previous sample and run one at a time, the gain is computed and applied to
+    /// [`BLOCK_SIZE`] samples at a time without matching on the parameters for every sample,
+    /// which about halves the time per sample, see `block_benchmark`. Parameters the block path
+    /// does not support, see [`supports_block`], fall back to processing every sample.
+    pub fn process_block(
+        &mut self,
+        samples: &mut [f32],
+        p: &RawParameters,
+    ) -> Result<(), &'static str> {
+        if !supports_block(p) {
+            for sample in samples.iter_mut() {
+                self.process_samples(sample, *p, None)?;
+            }
+            return Ok(());
+        }
 
-        let distance_from_threshold = p.threshold - abs_sample;
+        let c = self.prepare(p);
+        let waveshaper =
+            p.mode == DynamicsMode::Compressor && p.architecture == Architecture::Character;
 
-        let mix = sigmoid(distance_from_threshold);
-        *sample = *sample * (1.0 - mix) + wet * mix;
+        for samples in samples.chunks_mut(BLOCK_SIZE) {
+            let length = samples.len();
+            let mut input = [0.0; BLOCK_SIZE];
+            input[..length].copy_from_slice(samples);
 
-        *sample *= p.gain;
+            let mut start = 0;
+            while start < length {
+                self.process_chunk(&mut samples[start..], &c, waveshaper);
 
-        if let Some(logger) = &mut logger {
-            logger.write("mix", mix)?;
-            logger.write("after", *sample)?;
+                // Like `process_samples`, only the sample that went wrong is silenced and the
+                // samples after it are compressed again from silence.
+                match samples[start..]
+                    .iter()
+                    .position(|sample| !sample.is_finite())
+                {
+                    Some(offset) => {
+                        self.reset();
+                        samples[start + offset] = 0.0;
+                        start += offset + 1;
+                        samples[start..].copy_from_slice(&input[start..length]);
+                    }
+                    None => break,
+                }
+            }
         }
 
         Ok(())
     }
 
+    /// Compresses up to [`BLOCK_SIZE`] samples for [`Algo::process_block`].
+    fn process_chunk(&mut self, samples: &mut [f32], c: &Prepared, waveshaper: bool) {
+        let p = &c.p;
+        let mut envelopes = [0.0; BLOCK_SIZE];
+        let mut makeups = [0.0; BLOCK_SIZE];
+        let mut dry = [0.0; BLOCK_SIZE];
+
+        for (((sample, envelope), smoothed), dry) in samples
+            .iter_mut()
+            .zip(envelopes.iter_mut())
+            .zip(makeups.iter_mut())
+            .zip(dry.iter_mut())
+        {
+            *sample = flush(*sample);
+            let level = self.detect(*sample, c);
+            *sample = self.lookahead.process(*sample, c.delay);
+            self.envelope = flush(self.follow(level, c, &c.timing));
+            self.makeup = c.makeup + self.makeup_coefficient * (self.makeup - c.makeup);
+
+            *envelope = self.envelope;
+            *smoothed = self.makeup;
+            *dry = *sample;
+        }
+
+        if waveshaper {
+            for (sample, envelope) in samples.iter_mut().zip(envelopes) {
+                (*sample, _) = character(*sample, envelope, c);
+            }
+        } else {
+            // Only the gain is needed here, not how much of the knee it is in.
+            let gains = &mut envelopes[..samples.len()];
+            clean_gains(gains, c);
+            for (sample, gain) in samples.iter_mut().zip(gains) {
+                *sample *= *gain;
+            }
+        }
+
+        if let Some(last) = samples.last() {
+            self.previous_output = flush(*last);
+        }
+
+        for ((sample, makeup), dry) in samples.iter_mut().zip(makeups).zip(dry) {
+            *sample = dry + (*sample * (p.gain * makeup) - dry) * p.mix;
+        }
+    }
+
+    /// Delays `sample` by the lookahead without compressing it. Channels that are not compressed
+    /// go through this to stay aligned with the ones that are.
+    pub fn delay(&mut self, sample: &mut f32, c: &Prepared) {
+        *sample = self.lookahead.process(flush(*sample), c.delay);
+    }
+
+    /// Runs the selected detector on the next sample after the sidechain filter. With the feedback
+    /// topology the detector reads the previous output instead of `sample`, [`Algo::key`] still
+    /// returns the filtered `sample`.
+    pub fn detect(&mut self, sample: f32, c: &Prepared) -> f32 {
+        let p = &c.p;
+        let sample = flush(sample);
+
+        // The ceiling has to hold for the signal itself, so the limiter always reads the
+        // unfiltered input with the true-peak detector.
+        let level = if p.mode == DynamicsMode::Limiter {
+            self.key = sample;
+            self.true_peak.process(sample)
+        } else {
+            self.key = self.filter_key(sample, p);
+            let key = match p.topology {
+                Topology::FeedForward => self.key,
+                Topology::Feedback if p.sidechain_filter == FilterType::Off => self.previous_output,
+                Topology::Feedback => self.feedback_filter.process(self.previous_output),
+            };
+
+            let detector: &mut dyn Detector = match p.detector {
+                DetectorMode::Peak => &mut self.peak,
+                DetectorMode::Rms => {
+                    self.rms.set_window(p.rms_window);
+                    &mut self.rms
+                }
+                DetectorMode::TruePeak => &mut self.true_peak,
+            };
+
+            detector.process(key)
+        };
+
+        // Huge input can overflow the sidechain filter or the detectors, their state would then
+        // never become finite again. With feedback the huge signal can also linger in the output
+        // of the models and overflow the detectors on every sample, so those start over as well.
+        if !level.is_finite() {
+            self.key = 0.0;
+            self.previous_output = 0.0;
+            self.vca.reset();
+            self.fet.reset();
+            self.opto.reset();
+            self.key_filter.reset();
+            self.feedback_filter.reset();
+            self.peak.reset();
+            self.rms.reset();
+            self.true_peak.reset();
+            return 0.0;
+        }
+
+        level
+    }
+
+    /// Parameters `p` as the selected model runs them, with the values derived from them. They
+    /// are only prepared again when they differ from the last ones, e.g. while a smoother moves.
+    /// Prepare once per sample and pass the result to [`Algo::detect`] and
+    /// [`Algo::process_with_level`].
+    pub fn prepare(&mut self, p: &RawParameters) -> Prepared {
+        let p = self.model(p.model).parameters(*p);
+
+        match self.prepared {
+            Some(prepared) if prepared.p == p => prepared,
+            _ => {
+                let prepared = Prepared::new(&p, self.sample_rate);
+                self.prepared = Some(prepared);
+                prepared
+            }
+        }
+    }
+
+    /// The selected analog model.
+    fn model(&mut self, model: AnalogModel) -> &mut dyn Model {
+        match model {
+            AnalogModel::Vca => &mut self.vca,
+            AnalogModel::Fet => &mut self.fet,
+            AnalogModel::Opto => &mut self.opto,
+        }
+    }
+
+    /// Input or sidechain signal of the last call to [`Algo::detect`] after the sidechain filter,
+    /// whatever the topology.
+    pub fn key(&self) -> f32 {
+        self.key
+    }
+
+    /// Runs `key` through the sidechain filter, recomputing the coefficients of both sidechain
+    /// filters when the settings changed.
+    fn filter_key(&mut self, key: f32, p: &RawParameters) -> f32 {
+        if p.sidechain_filter == FilterType::Off {
+            return key;
+        }
+
+        let settings = (
+            p.sidechain_filter,
+            p.sidechain_frequency,
+            p.sidechain_q,
+            p.sidechain_gain,
+        );
+
+        if settings != self.key_filter_settings {
+            let coefficients = Coefficients::new(
+                p.sidechain_filter,
+                self.sample_rate,
+                p.sidechain_frequency,
+                p.sidechain_q,
+                p.sidechain_gain,
+            );
+            self.key_filter.set_coefficients(coefficients);
+            self.feedback_filter.set_coefficients(coefficients);
+            self.key_filter_settings = settings;
+        }
+
+        self.key_filter.process(key)
+    }
+
+    /// Ballistics of `c` with the attack and release times the selected model scales them to on
+    /// this sample.
+    fn timing(&mut self, c: &Prepared) -> Timing {
+        let (attack_scale, release_scale) = self.model(c.p.model).time_scale();
+
+        if attack_scale == 1.0 && release_scale == 1.0 {
+            c.timing
+        } else {
+            Timing::new(
+                c.p.attack * attack_scale * self.sample_rate,
+                c.p.release * release_scale * self.sample_rate,
+            )
+        }
+    }
+
+    /// Moves the envelope one sample towards `level` according to the selected ballistics.
+    fn follow(&mut self, level: f32, c: &Prepared, t: &Timing) -> f32 {
+        self.holding = level < self.envelope && self.hold_remaining >= 1.0;
+        if level >= self.envelope {
+            self.hold_remaining = c.hold_samples;
+        } else if self.holding {
+            self.hold_remaining -= 1.0;
+            return self.envelope;
+        }
+
+        let attacking = level > self.envelope;
+        let coefficient = if attacking {
+            t.attack_coefficient
+        } else {
+            t.release_coefficient
+        };
+
+        match c.p.ballistics {
+            Ballistics::Linear => {
+                if attacking {
+                    (self.envelope + t.attack_step).min(level)
+                } else {
+                    (self.envelope - t.release_step).max(level)
+                }
+            }
+            Ballistics::Exponential => one_pole(self.envelope, level, coefficient),
+            Ballistics::LogRelease => {
+                if attacking {
+                    one_pole(self.envelope, level, coefficient)
+                } else {
+                    (self.envelope * t.log_release_factor).max(level)
+                }
+            }
+            Ballistics::AutoRelease => {
+                self.slow_envelope = flush(one_pole(self.slow_envelope, level, t.slow_coefficient));
+
+                one_pole(self.envelope, level, coefficient).max(self.slow_envelope)
+            }
+        }
+    }
+
+    /// Opens or closes the gate on the envelope and moves the gate gain one sample towards open
+    /// or the range, with the attack and release as time constants.
+    fn follow_gate(&mut self, c: &Prepared, t: &Timing) -> f32 {
+        if self.envelope >= c.p.threshold {
+            self.gate_open = true;
+        } else if self.envelope < c.close_threshold {
+            self.gate_open = false;
+        }
+
+        let target = if self.gate_open { 1.0 } else { c.range_gain };
+        let coefficient = if target > self.gate_gain {
+            t.attack_coefficient
+        } else {
+            t.release_coefficient
+        };
+
+        one_pole(self.gate_gain, target, coefficient)
+    }
+
     pub fn get_envelope(&self) -> f32 {
         self.envelope
     }
+
+    /// Whether the envelope was kept at its peak by the hold on the last sample.
+    pub fn is_holding(&self) -> bool {
+        self.holding
+    }
+}
+
+/// Whether [`Algo::process_block`] can process with `p` as a block. The feedback topology needs
+/// every output before the next detection, and the gate, the limiter and the models other than
+/// VCA keep state in the gain stage, so those run one sample at a time.
+pub fn supports_block(p: &RawParameters) -> bool {
+    p.topology == Topology::FeedForward
+        && p.model == AnalogModel::Vca
+        && matches!(
+            p.mode,
+            DynamicsMode::Compressor | DynamicsMode::UpwardCompressor | DynamicsMode::Expander
+        )
+}
+
+/// Bends `sample` around the threshold with the ratio at `envelope`, which is the character
+/// architecture. Returns the new sample and the amount of compressed signal in it.
+fn character(sample: f32, envelope: f32, c: &Prepared) -> (f32, f32) {
+    let p = &c.p;
+    let abs_sample = sample.abs();
+
+    let (bend, ratio, mix) = match p.knee {
+        Knee::Steepness => (
+            p.threshold,
+            envelope_ratio(envelope, c),
+            knee_mix(abs_sample, c),
+        ),
+        // Bends from the start of the knee with the ratio that puts the peak at the envelope on
+        // the static curve, so the quadratic knee of the gain computer shapes the waveform.
+        Knee::Width => {
+            let ratio = if envelope > c.knee_start {
+                (compressor_curve(envelope, c, Knee::Width) - c.knee_start)
+                    / (envelope - c.knee_start)
+            } else {
+                1.0
+            };
+            (c.knee_start, ratio, 1.0)
+        }
+    };
+
+    let wet = if envelope > bend && sample > bend {
+        bend + (sample - bend) * ratio
+    } else if -envelope < -bend && sample < -bend {
+        -(bend + (abs_sample - bend) * ratio)
+    } else {
+        sample
+    };
+
+    (sample * (1.0 - mix) + wet * mix, mix)
+}
+
+/// Multiplies `sample` with the gain of the static curve at `envelope`, which is the clean
+/// architecture. Returns the new sample and the amount of compressed signal in it.
+fn clean(sample: f32, envelope: f32, c: &Prepared) -> (f32, f32) {
+    (sample * clean_gain(envelope, c), knee_mix(envelope, c))
+}
+
+/// Gain the clean architecture applies at `envelope`.
+pub fn clean_gain(envelope: f32, c: &Prepared) -> f32 {
+    if envelope > 0.0 {
+        static_curve(envelope, c) / envelope
+    } else {
+        1.0
+    }
+}
+
+/// Replaces every envelope in `envelopes` with [`clean_gain`] at it. The mode and the knee are
+/// matched once for the whole slice instead of for every sample.
+fn clean_gains(envelopes: &mut [f32], c: &Prepared) {
+    match (c.p.mode, c.p.knee) {
+        (DynamicsMode::Compressor, Knee::Steepness) => {
+            curve_gains(envelopes, c, DynamicsMode::Compressor, Knee::Steepness)
+        }
+        (DynamicsMode::Compressor, Knee::Width) => {
+            curve_gains(envelopes, c, DynamicsMode::Compressor, Knee::Width)
+        }
+        (DynamicsMode::UpwardCompressor, Knee::Steepness) => curve_gains(
+            envelopes,
+            c,
+            DynamicsMode::UpwardCompressor,
+            Knee::Steepness,
+        ),
+        (DynamicsMode::UpwardCompressor, Knee::Width) => {
+            curve_gains(envelopes, c, DynamicsMode::UpwardCompressor, Knee::Width)
+        }
+        (DynamicsMode::Expander, Knee::Steepness) => {
+            curve_gains(envelopes, c, DynamicsMode::Expander, Knee::Steepness)
+        }
+        (DynamicsMode::Expander, Knee::Width) => {
+            curve_gains(envelopes, c, DynamicsMode::Expander, Knee::Width)
+        }
+        _ => envelopes
+            .iter_mut()
+            .for_each(|envelope| *envelope = clean_gain(*envelope, c)),
+    }
+}
+
+/// [`clean_gains`] for one mode and knee.
+#[inline(always)]
+fn curve_gains(envelopes: &mut [f32], c: &Prepared, mode: DynamicsMode, knee: Knee) {
+    for envelope in envelopes {
+        *envelope = if *envelope > 0.0 {
+            curve(*envelope, c, mode, knee) / *envelope
+        } else {
+            1.0
+        };
+    }
+}
+
+/// Samples the lookahead delay line needs to hold at `sample_rate`. The plugin lengthens the
+/// lookahead by up to a host sample to round the latency up, which is at most
+/// [`MAX_OVERSAMPLING`] samples here.
+fn lookahead_capacity(sample_rate: f32) -> usize {
+    (MAX_LOOKAHEAD * sample_rate).ceil() as usize + TRUE_PEAK_LATENCY + MAX_OVERSAMPLING
+}
+
+/// Samples the audio path is delayed by when compressing with `p` at `sample_rate`.
+pub fn delay(p: &RawParameters, sample_rate: f32) -> f32 {
+    // The true-peak detector hears every sample late, the audio waits for it. The limiter
+    // always reads the true peak.
+    if p.mode == DynamicsMode::Limiter || p.detector == DetectorMode::TruePeak {
+        p.lookahead * sample_rate + TRUE_PEAK_LATENCY as f32
+    } else {
+        p.lookahead * sample_rate
+    }
+}
+
+/// Samples the limiter holds and averages its gain over, the whole samples of the lookahead plus
+/// the current sample. The oldest sample of the window is the one leaving the delay line.
+fn limiter_window(p: &RawParameters, sample_rate: f32) -> usize {
+    (p.lookahead * sample_rate) as usize + 1
+}
+
+/// One-pole coefficient for the makeup gain smoothing at `sample_rate`.
+fn makeup_coefficient(sample_rate: f32) -> f32 {
+    one_pole_coefficient(MAKEUP_SMOOTHING * sample_rate)
+}
+
+/// Coefficient of a one-pole lowpass with a time constant of `samples`.
+fn one_pole_coefficient(samples: f32) -> f32 {
+    (-1.0 / samples).exp()
+}
+
+/// `value`, or zero when it is subnormal, infinite or NaN. Subnormals slow down the recursive
+/// state by orders of magnitude while it decays, and a NaN or infinity would never leave it.
+pub(crate) fn flush(value: f32) -> f32 {
+    if value.is_normal() {
+        value
+    } else {
+        0.0
+    }
+}
+
+/// Moves `current` one sample towards `target` with a coefficient from
+/// [`one_pole_coefficient`].
+fn one_pole(current: f32, target: f32, coefficient: f32) -> f32 {
+    target + coefficient * (current - target)
+}
+
+/// Output level of the static curve for a steady input at `level`, i.e. once the envelope has
+/// settled on it.
+pub fn static_curve(level: f32, c: &Prepared) -> f32 {
+    curve(level, c, c.p.mode, c.p.knee)
+}
+
+/// [`static_curve`] with the mode and the knee passed in, so a loop that passes constants does
+/// not match on them for every sample once this is inlined.
+#[inline(always)]
+fn curve(level: f32, c: &Prepared, mode: DynamicsMode, knee: Knee) -> f32 {
+    let p = &c.p;
+    match mode {
+        DynamicsMode::Compressor => compressor_curve(level, c, knee),
+        DynamicsMode::UpwardCompressor => upward_curve(level, c, knee).min(level * c.boost_gain),
+        DynamicsMode::Expander => expander_curve(level, c, knee).max(level * c.range_gain),
+        DynamicsMode::Gate => {
+            if level >= p.threshold {
+                level
+            } else {
+                level * c.range_gain
+            }
+        }
+        DynamicsMode::Limiter => (level * p.gain).min(c.limiter_ceiling),
+    }
+}
+
+/// Static curve of [`DynamicsMode::Compressor`].
+#[inline(always)]
+fn compressor_curve(level: f32, c: &Prepared, knee: Knee) -> f32 {
+    let p = &c.p;
+    match knee {
+        Knee::Steepness => {
+            let wet = if level > p.threshold {
+                p.threshold + (level - p.threshold) * envelope_ratio(level, c)
+            } else {
+                level
+            };
+
+            let mix = knee_mix(level, c);
+            level * (1.0 - mix) + wet * mix
+        }
+        Knee::Width => util::db_to_gain(gain_computer(util::gain_to_db(level), c)),
+    }
+}
+
+/// Static curve of [`DynamicsMode::UpwardCompressor`] without the range.
+#[inline(always)]
+fn upward_curve(level: f32, c: &Prepared, knee: Knee) -> f32 {
+    let p = &c.p;
+    match knee {
+        Knee::Steepness => {
+            let wet = if level < p.threshold {
+                p.threshold * (level / p.threshold).powf(1.0 / p.ratio)
+            } else {
+                level
+            };
+
+            let mix = knee_mix(level, c);
+            wet * (1.0 - mix) + level * mix
+        }
+        Knee::Width => util::db_to_gain(upward_gain_computer(util::gain_to_db(level), c)),
+    }
+}
+
+/// Static curve of [`DynamicsMode::Expander`] without the range.
+#[inline(always)]
+fn expander_curve(level: f32, c: &Prepared, knee: Knee) -> f32 {
+    let p = &c.p;
+    match knee {
+        Knee::Steepness => {
+            let wet = if level < p.threshold {
+                level * (level / p.threshold).powf(p.ratio - 1.0)
+            } else {
+                level
+            };
+
+            let mix = knee_mix(level, c);
+            wet * (1.0 - mix) + level * mix
+        }
+        Knee::Width => util::db_to_gain(expander_gain_computer(util::gain_to_db(level), c)),
+    }
+}
+
+/// Gain that brings a full scale signal back to full scale after the static curve.
+fn makeup_gain(c: &Prepared) -> f32 {
+    // The limiter does not get makeup, it would push the signal over the ceiling.
+    if c.p.mode == DynamicsMode::Limiter {
+        return 1.0;
+    }
+
+    let compressed = static_curve(MAKEUP_REFERENCE, c);
+
+    if compressed > 0.0 {
+        MAKEUP_REFERENCE / compressed
+    } else {
+        1.0
+    }
+}
+
+/// Factor the part of the signal above the threshold is scaled with at `envelope`. Goes from one
+/// at the threshold to one over the ratio at full scale.
+fn envelope_ratio(envelope: f32, c: &Prepared) -> f32 {
+    let p = &c.p;
+    1.0 / (((envelope - p.threshold) * c.envelope_scaler) * (p.ratio - 1.0) + 1.0)
+}
+
+/// Amount in [0, 1] the compressed signal is mixed in at `level`. Zero well below the threshold,
+/// one well above it, with the selected knee in between.
+fn knee_mix(level: f32, c: &Prepared) -> f32 {
+    let p = &c.p;
+    match p.knee {
+        Knee::Steepness => 1.0 / (1.0 + (p.steepness * (p.threshold - level)).exp()),
+        Knee::Width => {
+            let distance_db = util::gain_to_db(level) - c.threshold_db;
+
+            if p.knee_width <= 0.0 {
+                if distance_db > 0.0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            } else {
+                (distance_db / p.knee_width + 0.5).clamp(0.0, 1.0)
+            }
+        }
+    }
+}
+
+/// Static curve in the dB domain with a quadratic knee of `p.knee_width` dB around the
+/// threshold. Returns the output level in dB for an input level in dB.
+fn gain_computer(level_db: f32, c: &Prepared) -> f32 {
+    let (p, threshold_db) = (&c.p, c.threshold_db);
+    let over = level_db - threshold_db;
+
+    if 2.0 * over <= -p.knee_width {
+        level_db
+    } else if 2.0 * over < p.knee_width {
+        let knee_position = over + p.knee_width / 2.0;
+        level_db + (1.0 / p.ratio - 1.0) * knee_position * knee_position / (2.0 * p.knee_width)
+    } else {
+        threshold_db + over / p.ratio
+    }
+}
+
+/// Mirror of [`gain_computer`] for the upward compressor, the knee and the ratio are below the
+/// threshold.
+fn upward_gain_computer(level_db: f32, c: &Prepared) -> f32 {
+    let (p, threshold_db) = (&c.p, c.threshold_db);
+    let under = level_db - threshold_db;
+
+    if 2.0 * under >= p.knee_width {
+        level_db
+    } else if 2.0 * under > -p.knee_width {
+        let knee_position = under - p.knee_width / 2.0;
+        level_db + (1.0 - 1.0 / p.ratio) * knee_position * knee_position / (2.0 * p.knee_width)
+    } else {
+        threshold_db + under / p.ratio
+    }
+}
+
+/// Mirror of [`gain_computer`] for the expander, the knee and the ratio are below the threshold.
+fn expander_gain_computer(level_db: f32, c: &Prepared) -> f32 {
+    let (p, threshold_db) = (&c.p, c.threshold_db);
+    let under = level_db - threshold_db;
+
+    if 2.0 * under >= p.knee_width {
+        level_db
+    } else if 2.0 * under > -p.knee_width {
+        let knee_position = under - p.knee_width / 2.0;
+        level_db - (p.ratio - 1.0) * knee_position * knee_position / (2.0 * p.knee_width)
+    } else {
+        threshold_db + under * p.ratio
+    }
+}
+
+/// Width in dB of the [`Knee::Width`] knee that goes from mixing in 10% to 90% of the compressed
+/// signal over the same levels as the sigmoid with `steepness` at `threshold`. Used to carry
+/// presets that only know the steepness over to [`Knee::Width`].
+pub fn steepness_to_knee_width(steepness: f32, threshold: f32) -> f32 {
+    let half_width = 9.0f32.ln() / steepness;
+    let lower = (threshold - half_width).max(f32::EPSILON);
+    let span_db = util::gain_to_db(threshold + half_width) - util::gain_to_db(lower);
+
+    // The width knee mixes linearly in dB, so 10% to 90% is 80% of its width.
+    span_db / 0.8
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::oversampling::{Oversampler, Oversampling};
+
+    /// Settings the tests start from, a hard-kneed clean compressor.
+    pub(crate) fn parameters() -> RawParameters {
+        RawParameters {
+            threshold: 0.1,
+            ratio: 4.0,
+            steepness: 8.0,
+            knee: Knee::Width,
+            knee_width: 0.0,
+            mode: DynamicsMode::Compressor,
+            model: AnalogModel::Vca,
+            range: 40.0,
+            hysteresis: 3.0,
+            ceiling: 1.0,
+            attack: 0.01,
+            release: 0.1,
+            hold: 0.0,
+            gain: 1.0,
+            ballistics: Ballistics::Exponential,
+            architecture: Architecture::Clean,
+            topology: Topology::FeedForward,
+            detector: DetectorMode::Peak,
+            rms_window: 0.01,
+            sidechain_filter: FilterType::Off,
+            sidechain_frequency: 100.0,
+            sidechain_q: 0.7,
+            sidechain_gain: 0.0,
+            lookahead: 0.0,
+            auto_makeup: false,
+            mix: 1.0,
+        }
+    }
+
+    /// Feeds a constant `level` until the envelope satisfies `done` and returns how long that
+    /// took in seconds.
+    fn seconds_until(
+        algo: &mut Algo,
+        p: &RawParameters,
+        level: f32,
+        sample_rate: f32,
+        done: impl Fn(f32) -> bool,
+    ) -> f32 {
+        let mut samples = 0;
+        while !done(algo.get_envelope()) {
+            let mut sample = level;
+            algo.process_samples(&mut sample, *p, None).unwrap();
+            samples += 1;
+            assert!(
+                samples < 10 * sample_rate as usize,
+                "envelope never got there"
+            );
+        }
+
+        samples as f32 / sample_rate
+    }
+
+    #[test]
+    fn time_constants_do_not_depend_on_the_sample_rate() {
+        let p = parameters();
+        let charged = 1.0 - (-1.0f32).exp();
+
+        for sample_rate in [44100.0, 48000.0, 96000.0, 192000.0] {
+            let mut algo = Algo::new(sample_rate);
+            let tolerance = 1.5 / sample_rate;
+
+            let attack = seconds_until(&mut algo, &p, 1.0, sample_rate, |e| e >= charged);
+            assert!(
+                (attack - p.attack).abs() < tolerance,
+                "{sample_rate} Hz: {attack} s"
+            );
+
+            seconds_until(&mut algo, &p, 1.0, sample_rate, |e| e >= 0.9999);
+            let release = seconds_until(&mut algo, &p, 0.0, sample_rate, |e| e <= 1.0 - charged);
+            // The envelope starts the release just below full scale.
+            assert!(
+                (release - p.release).abs() < 0.001,
+                "{sample_rate} Hz: {release} s"
+            );
+        }
+    }
+
+    #[test]
+    fn ballistics_follow_their_time_constant_definitions() {
+        let sample_rate = 48000.0;
+        let tolerance = 1.5 / sample_rate;
+        let full_scale = |e: f32| e >= 1.0;
+
+        let p = RawParameters {
+            ballistics: Ballistics::Linear,
+            ..parameters()
+        };
+        let mut algo = Algo::new(sample_rate);
+        let attack = seconds_until(&mut algo, &p, 1.0, sample_rate, full_scale);
+        assert!((attack - p.attack).abs() < tolerance, "linear: {attack} s");
+
+        let p = RawParameters {
+            ballistics: Ballistics::Exponential,
+            ..parameters()
+        };
+        let mut algo = Algo::new(sample_rate);
+        let charged = |e: f32| e >= 1.0 - (-1.0f32).exp();
+        let attack = seconds_until(&mut algo, &p, 1.0, sample_rate, charged);
+        assert!(
+            (attack - p.attack).abs() < tolerance,
+            "exponential: {attack} s"
+        );
+
+        let p = RawParameters {
+            ballistics: Ballistics::LogRelease,
+            ..parameters()
+        };
+        let mut algo = Algo::new(sample_rate);
+        seconds_until(&mut algo, &p, 1.0, sample_rate, |e| e >= 0.9999);
+        let floor = util::db_to_gain(-LOG_RELEASE_RANGE_DB);
+        let release = seconds_until(&mut algo, &p, 0.0, sample_rate, |e| e <= floor);
+        assert!(
+            (release - p.release).abs() < tolerance,
+            "log release: {release} s"
+        );
+    }
+
+    #[test]
+    fn character_width_knee_follows_the_gain_computer() {
+        let p = RawParameters {
+            threshold: util::db_to_gain(-20.0),
+            knee_width: 12.0,
+            architecture: Architecture::Character,
+            ..parameters()
+        };
+        let c = Prepared::new(&p, 48000.0);
+
+        // Below, inside and above the knee.
+        for level_db in [-30.0, -24.0, -20.0, -16.0, -6.0, 0.0] {
+            let level = util::db_to_gain(level_db);
+            let expected = gain_computer(level_db, &c);
+
+            for sign in [1.0, -1.0] {
+                let (wet, _) = character(sign * level, level, &c);
+                let wet_db = util::gain_to_db(sign * wet);
+                assert!(
+                    (wet_db - expected).abs() < 0.01,
+                    "{level_db} dB: {wet_db} dB"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn converted_knee_width_spans_the_same_levels() {
+        // Level in dB where the knee of `c` mixes in `amount` of the compressed signal.
+        let level_at = |amount: f32, c: &Prepared| {
+            let (mut low, mut high) = (-120.0, 20.0);
+            for _ in 0..60 {
+                let middle = (low + high) / 2.0;
+                if knee_mix(util::db_to_gain(middle), c) < amount {
+                    low = middle;
+                } else {
+                    high = middle;
+                }
+            }
+            low
+        };
+        let span = |c: &Prepared| level_at(0.9, c) - level_at(0.1, c);
+
+        for (steepness, threshold_db) in [(10.0, -6.0), (30.0, -12.0), (50.0, -20.0)] {
+            let threshold = util::db_to_gain(threshold_db);
+            let sigmoid = RawParameters {
+                threshold,
+                steepness,
+                knee: Knee::Steepness,
+                ..parameters()
+            };
+            let width = RawParameters {
+                threshold,
+                knee: Knee::Width,
+                knee_width: steepness_to_knee_width(steepness, threshold),
+                ..parameters()
+            };
+
+            let sigmoid_span = span(&Prepared::new(&sigmoid, 48000.0));
+            let width_span = span(&Prepared::new(&width, 48000.0));
+            assert!(
+                (sigmoid_span - width_span).abs() < 0.01,
+                "steepness {steepness}: {sigmoid_span} dB against {width_span} dB"
+            );
+        }
+    }
+
+    #[test]
+    fn true_peak_detector_is_aligned_with_the_audio() {
+        let p = RawParameters {
+            attack: 0.0,
+            release: 1.0,
+            detector: DetectorMode::TruePeak,
+            ..parameters()
+        };
+        let mut algo = Algo::new(48000.0);
+
+        let output: Vec<f32> = (0..200)
+            .map(|n| {
+                let mut sample = if n < 100 { 0.0 } else { 1.0 };
+                algo.process_samples(&mut sample, p, None).unwrap();
+                sample
+            })
+            .collect();
+
+        // The first sample of the step is already turned down to the static curve, or slightly
+        // further by the overshoot the interpolator sees on a step.
+        let first = output.iter().find(|sample| sample.abs() > 1e-3).unwrap();
+        let expected = static_curve(1.0, &Prepared::new(&p, 48000.0));
+        assert!(*first <= expected, "{first} against {expected}");
+    }
+
+    #[test]
+    fn feedback_has_a_softer_effective_ratio() {
+        let sample_rate = 48000.0;
+        let input_db = -5.0;
+
+        // Level above the threshold in dB after a second of a constant input.
+        let over_db = |topology| {
+            let p = RawParameters {
+                topology,
+                ..parameters()
+            };
+            let mut algo = Algo::new(sample_rate);
+            let mut sample = 0.0;
+            for _ in 0..sample_rate as usize {
+                sample = util::db_to_gain(input_db);
+                algo.process_samples(&mut sample, p, None).unwrap();
+            }
+
+            util::gain_to_db(sample) - util::gain_to_db(p.threshold)
+        };
+
+        let ratio = parameters().ratio;
+        let input_over_db = input_db - util::gain_to_db(parameters().threshold);
+
+        let feed_forward = input_over_db / over_db(Topology::FeedForward);
+        assert!((feed_forward - ratio).abs() < 0.01, "{feed_forward}");
+
+        // The detector hears the output, so the gain reduction is `1 - 1 / ratio` times how far
+        // the output is over the threshold, and the input ends up `2 - 1 / ratio` times as far.
+        let feedback = input_over_db / over_db(Topology::Feedback);
+        assert!((feedback - (2.0 - 1.0 / ratio)).abs() < 0.01, "{feedback}");
+    }
+
+    #[test]
+    fn key_is_the_filtered_input_with_either_topology() {
+        let sample_rate = 48000.0;
+
+        for topology in [Topology::FeedForward, Topology::Feedback] {
+            let p = RawParameters {
+                topology,
+                sidechain_filter: FilterType::HighPass,
+                ..parameters()
+            };
+            let mut algo = Algo::new(sample_rate);
+            let mut filter = Biquad::new(Coefficients::new(
+                p.sidechain_filter,
+                sample_rate,
+                p.sidechain_frequency,
+                p.sidechain_q,
+                p.sidechain_gain,
+            ));
+
+            for n in 0..1000 {
+                let sample = 0.5 * (n as f32 * 0.01).sin();
+                let c = algo.prepare(&p);
+                let level = algo.detect(sample, &c);
+                assert_eq!(algo.key(), filter.process(sample), "{topology:?}");
+
+                let mut output = sample;
+                algo.process_with_level(&mut output, level, &c, None)
+                    .unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn hold_keeps_the_envelope_flat_before_the_release() {
+        let sample_rate = 48000.0;
+        let p = RawParameters {
+            attack: 0.0,
+            release: 0.05,
+            hold: 0.02,
+            ..parameters()
+        };
+        let mut algo = Algo::new(sample_rate);
+        for _ in 0..100 {
+            let mut sample = 0.8;
+            algo.process_samples(&mut sample, p, None).unwrap();
+        }
+        let peak = algo.get_envelope();
+        assert!(!algo.is_holding());
+
+        // The level drops to silence, the envelope stays at the peak while holding.
+        let mut held: usize = 0;
+        loop {
+            let mut sample = 0.0;
+            algo.process_samples(&mut sample, p, None).unwrap();
+            if !algo.is_holding() {
+                break;
+            }
+            assert_eq!(algo.get_envelope(), peak);
+            held += 1;
+        }
+        let hold_samples = (p.hold * sample_rate) as usize;
+        assert!(
+            held.abs_diff(hold_samples) <= 1,
+            "{held} against {hold_samples}"
+        );
+
+        // Then it releases.
+        assert!(algo.get_envelope() < peak);
+        for _ in 0..(p.release * sample_rate) as usize {
+            let mut sample = 0.0;
+            algo.process_samples(&mut sample, p, None).unwrap();
+        }
+        assert!(algo.get_envelope() < 0.5 * peak, "{}", algo.get_envelope());
+    }
+
+    #[test]
+    fn hold_steadies_the_envelope_on_a_40_hz_sine() {
+        let sample_rate = 48000.0;
+
+        // Difference between the highest and the lowest envelope over the last half second of two
+        // seconds of a 40 Hz sine.
+        let ripple = |hold: f32| {
+            let p = RawParameters {
+                attack: 0.001,
+                release: 0.005,
+                hold,
+                ..parameters()
+            };
+            let mut algo = Algo::new(sample_rate);
+            let envelopes: Vec<f32> = (0..2 * sample_rate as usize)
+                .map(|n| {
+                    let phase = 2.0 * std::f32::consts::PI * 40.0 * n as f32 / sample_rate;
+                    let mut sample = 0.8 * phase.sin();
+                    algo.process_samples(&mut sample, p, None).unwrap();
+                    algo.get_envelope()
+                })
+                .collect();
+            let settled = &envelopes[3 * sample_rate as usize / 2..];
+
+            settled
+                .iter()
+                .fold(0.0, |highest: f32, envelope| highest.max(*envelope))
+                - settled
+                    .iter()
+                    .fold(1.0, |lowest: f32, envelope| lowest.min(*envelope))
+        };
+
+        // Without the hold, the envelope falls a good part of the way between the peaks of the
+        // rectified sine. Holding for longer than the 12.5 ms between them keeps it at the peak.
+        let unheld = ripple(0.0);
+        let held = ripple(0.015);
+        assert!(unheld > 0.2, "{unheld}");
+        assert!(held < 0.01, "{held} against {unheld}");
+    }
+
+    #[test]
+    fn auto_release_is_slow_after_sustained_compression() {
+        let sample_rate = 48000.0;
+        let p = RawParameters {
+            ballistics: Ballistics::AutoRelease,
+            attack: 0.001,
+            ..parameters()
+        };
+
+        // Seconds the envelope takes to fall to a tenth after `seconds` of full scale input.
+        let release_after = |seconds: f32| {
+            let mut algo = Algo::new(sample_rate);
+            for _ in 0..(seconds * sample_rate) as usize {
+                let mut sample = 1.0;
+                algo.process_samples(&mut sample, p, None).unwrap();
+            }
+            seconds_until(&mut algo, &p, 0.0, sample_rate, |e| e <= 0.1)
+        };
+
+        // After a burst only the fast stage is charged, a one-pole falls to a tenth in ln(10)
+        // time constants.
+        let burst = release_after(0.01);
+        let fast = p.release * 10.0f32.ln();
+        assert!((burst - fast).abs() < 0.1 * fast, "{burst} s after a burst");
+
+        // After a sustained tone the slow stage holds the envelope up.
+        let sustained = release_after(5.0);
+        let slow = fast * AUTO_RELEASE_SLOW_FACTOR;
+        assert!(
+            sustained > 0.8 * slow,
+            "{sustained} s after a sustained tone"
+        );
+    }
+
+    #[test]
+    fn gate_stays_open_between_the_close_threshold_and_the_threshold() {
+        let sample_rate = 48000.0;
+        let p = RawParameters {
+            mode: DynamicsMode::Gate,
+            attack: 0.001,
+            release: 0.001,
+            ..parameters()
+        };
+        let c = Prepared::new(&p, sample_rate);
+        let between = (c.close_threshold + p.threshold) / 2.0;
+        let mut algo = Algo::new(sample_rate);
+
+        // Gain of the gate after a tenth of a second at `level`.
+        let mut gain_at = |level: f32| {
+            let mut output = level;
+            for _ in 0..sample_rate as usize / 10 {
+                output = level;
+                algo.process_samples(&mut output, p, None).unwrap();
+            }
+            output / level
+        };
+
+        // Opened above the threshold, it stays open until the envelope falls below the close
+        // threshold, and then stays closed until it rises above the threshold again.
+        for (level, expected) in [
+            (2.0 * p.threshold, 1.0),
+            (between, 1.0),
+            (c.close_threshold / 2.0, c.range_gain),
+            (between, c.range_gain),
+            (2.0 * p.threshold, 1.0),
+        ] {
+            let gain = gain_at(level);
+            assert!(
+                (gain - expected).abs() < 1e-4,
+                "{level}: {gain} against {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn expander_and_gate_stop_at_the_range() {
+        let sample_rate = 48000.0;
+
+        for (mode, knee) in [
+            (DynamicsMode::Expander, Knee::Steepness),
+            (DynamicsMode::Expander, Knee::Width),
+            (DynamicsMode::Gate, Knee::Width),
+        ] {
+            let p = RawParameters {
+                mode,
+                knee,
+                knee_width: 6.0,
+                ratio: 10.0,
+                range: 20.0,
+                ..parameters()
+            };
+            let c = Prepared::new(&p, sample_rate);
+            let floor = c.range_gain * (1.0 - 1e-4);
+
+            for level in (0..90).map(|step| util::db_to_gain(-90.0 + step as f32)) {
+                let gain = static_curve(level, &c) / level;
+                assert!(gain >= floor, "{mode:?}, {knee:?}: {gain} at {level}");
+            }
+
+            // Far below the threshold the gain sits on the range, the sigmoid of the steepness knee
+            // leaves part of the signal uncompressed there.
+            let mut algo = Algo::new(sample_rate);
+            for n in 0..sample_rate as usize {
+                let input =
+                    0.001 * (2.0 * std::f32::consts::PI * 100.0 * n as f32 / sample_rate).sin();
+                let mut output = input;
+                algo.process_samples(&mut output, p, None).unwrap();
+
+                if n > sample_rate as usize / 2 && input.abs() > 1e-4 {
+                    let gain = output / input;
+                    assert!(gain >= floor, "{mode:?}, {knee:?}: {gain}");
+                    if knee == Knee::Width {
+                        assert!(gain < 1.1 * c.range_gain, "{mode:?}, {knee:?}: {gain}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn upward_compressor_leaves_loud_signals_alone() {
+        let sample_rate = 48000.0;
+
+        for knee in [Knee::Steepness, Knee::Width] {
+            for architecture in [Architecture::Clean, Architecture::Character] {
+                let p = RawParameters {
+                    mode: DynamicsMode::UpwardCompressor,
+                    knee,
+                    knee_width: 6.0,
+                    architecture,
+                    ..parameters()
+                };
+                let c = Prepared::new(&p, sample_rate);
+                // Above the threshold and the width knee.
+                let lowest = p.threshold * util::db_to_gain(p.knee_width / 2.0);
+
+                for level in [lowest, 0.2, 0.5, 1.0] {
+                    let output = static_curve(level, &c);
+                    assert!(
+                        (output - level).abs() < 1e-6 * level,
+                        "{knee:?}, {architecture:?}: {level} becomes {output}"
+                    );
+                }
+
+                let mut algo = Algo::new(sample_rate);
+                for n in 0..sample_rate as usize {
+                    let input =
+                        0.5 * (2.0 * std::f32::consts::PI * 100.0 * n as f32 / sample_rate).sin();
+                    let mut output = input;
+                    algo.process_samples(&mut output, p, None).unwrap();
+
+                    // Once the envelope settled above the knee.
+                    if n > sample_rate as usize / 10 {
+                        assert!(
+                            (output - input).abs() < 1e-6,
+                            "{knee:?}, {architecture:?}: {input} becomes {output}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn limiter_keeps_the_true_peak_below_the_ceiling() {
+        let resources = concat!(env!("CARGO_MANIFEST_DIR"), "/resources");
+        let mut files: Vec<_> = std::fs::read_dir(resources)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "wav"))
+            .collect();
+        files.sort();
+        assert!(!files.is_empty());
+
+        for path in files {
+            let mut reader = hound::WavReader::open(&path).unwrap();
+            let spec = reader.spec();
+            let scale = (1 << (spec.bits_per_sample - 1)) as f32;
+            let samples: Vec<f32> = reader
+                .samples::<i32>()
+                .map(|sample| sample.unwrap() as f32 / scale)
+                .collect();
+
+            let sample_rate = spec.sample_rate as f32;
+            // The analog models do not color the limiter, so the VCA stands for all of them.
+            let p = RawParameters {
+                mode: DynamicsMode::Limiter,
+                ceiling: 0.5,
+                gain: 4.0,
+                // Not a whole number of samples, so the fractional delay after the gain is
+                // checked too.
+                lookahead: 0.00523,
+                release: 0.05,
+                ..parameters()
+            };
+
+            // Every channel is limited on its own.
+            let channels = spec.channels as usize;
+            for channel in 0..channels {
+                let mut algo = Algo::new(sample_rate);
+                // The half-band filters of the oversampler reconstruct the output at eight times
+                // the rate with far longer filters than the detector of the limiter.
+                let mut oversampler = Oversampler::new();
+                let mut highest: f32 = 0.0;
+
+                for sample in samples.iter().skip(channel).step_by(channels) {
+                    let mut output = *sample;
+                    algo.process_samples(&mut output, p, None).unwrap();
+                    for value in oversampler.upsample(output, Oversampling::X8) {
+                        highest = highest.max(value.abs());
+                    }
+                }
+
+                assert!(
+                    highest <= p.ceiling,
+                    "{}: {} dB over",
+                    path.display(),
+                    util::gain_to_db(highest / p.ceiling)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn latency_is_the_delay_of_an_impulse() {
+        let lookahead = RawParameters {
+            threshold: 1.0,
+            lookahead: 0.00123,
+            ..parameters()
+        };
+        let true_peak = RawParameters {
+            detector: DetectorMode::TruePeak,
+            ..lookahead
+        };
+        let limiter = RawParameters {
+            mode: DynamicsMode::Limiter,
+            ..lookahead
+        };
+
+        for (p, oversampling) in [
+            (lookahead, Oversampling::X1),
+            (true_peak, Oversampling::X1),
+            (true_peak, Oversampling::X4),
+            (limiter, Oversampling::X1),
+        ] {
+            // The same as the plugin reports and pads the lookahead with.
+            let sample_rate = 48000.0 * oversampling.factor() as f32;
+            let (latency, padding) = oversampling.whole_latency(delay(&p, sample_rate));
+            let p = RawParameters {
+                lookahead: p.lookahead + padding / sample_rate,
+                ..p
+            };
+
+            let mut algo = Algo::new(sample_rate);
+            let mut oversampler = Oversampler::new();
+            let output: Vec<f32> = (0..200)
+                .map(|n| {
+                    let input = if n == 0 { 0.5 } else { 0.0 };
+                    let mut samples = oversampler.upsample(input, oversampling);
+                    for sample in &mut samples[..oversampling.factor()] {
+                        algo.process_samples(sample, p, None).unwrap();
+                    }
+                    oversampler.downsample(samples, oversampling)
+                })
+                .collect();
+
+            let (peak, _) = output
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()))
+                .unwrap();
+            assert_eq!(peak, latency as usize, "{:?} {oversampling:?}", p.mode);
+            if oversampling == Oversampling::X1 {
+                assert!(
+                    (output[peak] - 0.5).abs() < 1e-3,
+                    "{:?}: {}",
+                    p.mode,
+                    output[peak]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn lookahead_sweep_does_not_click() {
+        let sample_rate = 48000.0;
+        let frequency = 500.0;
+
+        for mode in [DynamicsMode::Compressor, DynamicsMode::Limiter] {
+            let mut algo = Algo::new(sample_rate);
+            let output: Vec<f32> = (0..sample_rate as usize)
+                .map(|n| {
+                    let time = n as f32 / sample_rate;
+                    // Glides from 1 ms to 5 ms, through every fraction of a sample.
+                    let p = RawParameters {
+                        mode,
+                        ceiling: 0.5,
+                        gain: if mode == DynamicsMode::Limiter {
+                            4.0
+                        } else {
+                            1.0
+                        },
+                        release: 0.05,
+                        lookahead: 0.001 + 0.004 * time,
+                        ..parameters()
+                    };
+                    let mut sample = 0.5 * (2.0 * std::f32::consts::PI * frequency * time).sin();
+                    algo.process_samples(&mut sample, p, None).unwrap();
+                    sample
+                })
+                .collect();
+
+            // A jump of the delay by a whole sample is a step in the slope of the sine, far larger
+            // than its curvature.
+            let curvature = output[sample_rate as usize / 10..]
+                .windows(3)
+                .map(|window| (window[0] - 2.0 * window[1] + window[2]).abs())
+                .fold(0.0, f32::max);
+            assert!(curvature < 0.01, "{mode:?}: {curvature}");
+        }
+    }
+
+    #[test]
+    fn fet_harmonics_have_no_offset() {
+        let sample_rate = 48000.0;
+        let p = RawParameters {
+            model: AnalogModel::Fet,
+            ratio: 200.0,
+            ..parameters()
+        };
+
+        // Mean of the output and its largest difference from the input over the second second of
+        // a 100 Hz sine at `amplitude`.
+        let run = |amplitude: f32| {
+            let mut algo = Algo::new(sample_rate);
+            let (mut sum, mut difference) = (0.0, 0.0f32);
+            for n in 0..2 * sample_rate as usize {
+                let phase = 2.0 * std::f32::consts::PI * 100.0 * n as f32 / sample_rate;
+                let input = amplitude * phase.sin();
+                let mut sample = input;
+                algo.process_samples(&mut sample, p, None).unwrap();
+                if n >= sample_rate as usize {
+                    sum += sample;
+                    difference = difference.max((sample - input).abs());
+                }
+            }
+            (sum / sample_rate, difference)
+        };
+
+        let (mean, _) = run(0.9);
+        assert!(mean.abs() < 1e-3, "{mean}");
+
+        // Below the threshold the FET leaves the signal alone.
+        let (_, difference) = run(0.05);
+        assert!(difference < 1e-6, "{difference}");
+    }
+
+    #[test]
+    fn opto_slows_the_release_without_preparing_again() {
+        let sample_rate = 48000.0;
+
+        // Seconds the envelope takes to fall to a tenth after `seconds` of full scale input.
+        let release_after = |model, seconds: f32| {
+            let p = RawParameters {
+                model,
+                ..parameters()
+            };
+            let mut algo = Algo::new(sample_rate);
+            for _ in 0..(seconds * sample_rate) as usize {
+                let mut sample = 1.0;
+                algo.process_samples(&mut sample, p, None).unwrap();
+            }
+
+            // The timing of the model lives outside the cached parameters.
+            assert!(algo.prepared.is_some_and(|prepared| prepared.p == p));
+
+            seconds_until(&mut algo, &p, 0.0, sample_rate, |e| e <= 0.1)
+        };
+
+        let vca = release_after(AnalogModel::Vca, 3.0);
+        let opto = release_after(AnalogModel::Opto, 3.0);
+        assert!(opto > 2.0 * vca, "{opto} s against {vca} s");
+    }
+
+    /// Bursts of a sine with a little noise, a NaN, an infinity and a sample that overflows with
+    /// the gain.
+    fn block_test_signal(length: usize) -> Vec<f32> {
+        let mut signal: Vec<f32> = (0..length)
+            .map(|n| {
+                let amplitude = if (n / 4800) % 2 == 0 { 0.9 } else { 0.05 };
+                let phase = 2.0 * std::f32::consts::PI * 440.0 * n as f32 / 48000.0;
+                let noise = (n * 7919 % 1000) as f32 / 500.0 - 1.0;
+                amplitude * phase.sin() + 0.01 * noise
+            })
+            .collect();
+        signal[1000] = f32::NAN;
+        signal[2000] = f32::INFINITY;
+        signal[3000] = f32::MAX;
+        signal
+    }
+
+    #[test]
+    fn block_matches_every_sample_where_supported() {
+        let input = block_test_signal(24000);
+        let mut tested = 0;
+
+        for mode in [
+            DynamicsMode::Compressor,
+            DynamicsMode::UpwardCompressor,
+            DynamicsMode::Expander,
+            DynamicsMode::Gate,
+            DynamicsMode::Limiter,
+        ] {
+            for model in [AnalogModel::Vca, AnalogModel::Fet, AnalogModel::Opto] {
+                for topology in [Topology::FeedForward, Topology::Feedback] {
+                    for architecture in [Architecture::Clean, Architecture::Character] {
+                        for ballistics in [
+                            Ballistics::Linear,
+                            Ballistics::Exponential,
+                            Ballistics::LogRelease,
+                            Ballistics::AutoRelease,
+                        ] {
+                            for detector in [
+                                DetectorMode::Peak,
+                                DetectorMode::Rms,
+                                DetectorMode::TruePeak,
+                            ] {
+                                let p = RawParameters {
+                                    mode,
+                                    model,
+                                    topology,
+                                    architecture,
+                                    ballistics,
+                                    detector,
+                                    knee: Knee::Steepness,
+                                    hold: 0.02,
+                                    lookahead: 0.003,
+                                    sidechain_filter: FilterType::HighPass,
+                                    auto_makeup: true,
+                                    gain: 1.3,
+                                    mix: 0.5,
+                                    ..parameters()
+                                };
+                                if !supports_block(&p) {
+                                    continue;
+                                }
+                                tested += 1;
+
+                                let mut per_sample = input.clone();
+                                let mut algo = Algo::new(48000.0);
+                                for sample in per_sample.iter_mut() {
+                                    algo.process_samples(sample, p, None).unwrap();
+                                }
+
+                                // Chunks that do not line up with the block size.
+                                let mut block = input.clone();
+                                let mut algo = Algo::new(48000.0);
+                                for chunk in block.chunks_mut(501) {
+                                    algo.process_block(chunk, &p).unwrap();
+                                }
+
+                                for (n, (a, b)) in per_sample.iter().zip(&block).enumerate() {
+                                    assert_eq!(a.to_bits(), b.to_bits(), "{p:?} at {n}: {a}, {b}");
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        assert!(tested > 0);
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture` to compare the speed of
+    /// [`Algo::process_block`] with the per-sample path at common buffer sizes.
+    #[test]
+    #[ignore]
+    fn block_benchmark() {
+        let input = block_test_signal(48000 * 10);
+        let p = parameters();
+
+        for buffer_size in [64, 512, 4096] {
+            let mut per_sample = input.clone();
+            let mut algo = Algo::new(48000.0);
+            let start = std::time::Instant::now();
+            for buffer in per_sample.chunks_mut(buffer_size) {
+                for sample in buffer.iter_mut() {
+                    algo.process_samples(sample, p, None).unwrap();
+                }
+            }
+            let per_sample_time = start.elapsed();
+
+            let mut block = input.clone();
+            let mut algo = Algo::new(48000.0);
+            let start = std::time::Instant::now();
+            for buffer in block.chunks_mut(buffer_size) {
+                algo.process_block(buffer, &p).unwrap();
+            }
+            let block_time = start.elapsed();
+
+            let nanoseconds =
+                |time: std::time::Duration| time.as_nanos() as f64 / input.len() as f64;
+            println!(
+                "{buffer_size} samples: {:.1} ns per sample, {:.1} ns as blocks",
+                nanoseconds(per_sample_time),
+                nanoseconds(block_time)
+            );
+            assert_eq!(per_sample, block);
+        }
+    }
+
+    #[test]
+    fn extreme_parameters_and_input_stay_finite() {
+        use rand::rngs::StdRng;
+        use rand::seq::SliceRandom;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(25);
+        let special = [
+            f32::NAN,
+            f32::INFINITY,
+            f32::NEG_INFINITY,
+            f32::MAX,
+            -f32::MAX,
+            1e30,
+            1e-40,
+            -1e-40,
+            f32::MIN_POSITIVE,
+            0.0,
+        ];
+
+        for case in 0..500 {
+            let p = RawParameters {
+                threshold: *[0.0316, 0.5, 0.999999, 1.0].choose(&mut rng).unwrap(),
+                ratio: *[1.0, 1.0001, 4.0, 200.0].choose(&mut rng).unwrap(),
+                steepness: *[0.0, 8.0, 1000.0].choose(&mut rng).unwrap(),
+                knee: *[Knee::Steepness, Knee::Width].choose(&mut rng).unwrap(),
+                knee_width: *[0.0, 6.0, 24.0].choose(&mut rng).unwrap(),
+                mode: *[
+                    DynamicsMode::Compressor,
+                    DynamicsMode::UpwardCompressor,
+                    DynamicsMode::Expander,
+                    DynamicsMode::Gate,
+                    DynamicsMode::Limiter,
+                ]
+                .choose(&mut rng)
+                .unwrap(),
+                model: *[AnalogModel::Vca, AnalogModel::Fet, AnalogModel::Opto]
+                    .choose(&mut rng)
+                    .unwrap(),
+                range: *[0.0, 40.0, 80.0].choose(&mut rng).unwrap(),
+                hysteresis: *[0.0, 12.0].choose(&mut rng).unwrap(),
+                ceiling: *[0.1, 0.891, 1.0].choose(&mut rng).unwrap(),
+                attack: *[0.0, 1e-6, 0.2].choose(&mut rng).unwrap(),
+                release: *[0.0, 1e-6, 0.2].choose(&mut rng).unwrap(),
+                hold: *[0.0, 0.5].choose(&mut rng).unwrap(),
+                gain: *[0.0, 1.0, 31.6].choose(&mut rng).unwrap(),
+                ballistics: *[
+                    Ballistics::Linear,
+                    Ballistics::Exponential,
+                    Ballistics::LogRelease,
+                    Ballistics::AutoRelease,
+                ]
+                .choose(&mut rng)
+                .unwrap(),
+                architecture: *[Architecture::Clean, Architecture::Character]
+                    .choose(&mut rng)
+                    .unwrap(),
+                topology: *[Topology::FeedForward, Topology::Feedback]
+                    .choose(&mut rng)
+                    .unwrap(),
+                detector: *[
+                    DetectorMode::Peak,
+                    DetectorMode::Rms,
+                    DetectorMode::TruePeak,
+                ]
+                .choose(&mut rng)
+                .unwrap(),
+                rms_window: *[0.001, 0.3].choose(&mut rng).unwrap(),
+                sidechain_filter: *[FilterType::Off, FilterType::HighPass, FilterType::LowShelf]
+                    .choose(&mut rng)
+                    .unwrap(),
+                sidechain_frequency: *[20.0, 20000.0].choose(&mut rng).unwrap(),
+                lookahead: *[0.0, 0.01].choose(&mut rng).unwrap(),
+                auto_makeup: rng.gen(),
+                mix: *[0.0, 0.5, 1.0].choose(&mut rng).unwrap(),
+                ..parameters()
+            };
+            let sample_rate = *[44100.0, 192000.0].choose(&mut rng).unwrap();
+            let mut algo = Algo::new(sample_rate);
+
+            // Loud noise with the special values mixed in, then silence.
+            let mut samples: Vec<f32> = (0..2048)
+                .map(|n| {
+                    if rng.gen_ratio(1, 50) {
+                        *special.choose(&mut rng).unwrap()
+                    } else if n < 1024 {
+                        rng.gen_range(-4.0..4.0)
+                    } else {
+                        0.0
+                    }
+                })
+                .collect();
+            if case % 2 == 0 {
+                algo.process_block(&mut samples, &p).unwrap();
+            } else {
+                for sample in samples.iter_mut() {
+                    algo.process_samples(sample, p, None).unwrap();
+                }
+            }
+
+            for (n, sample) in samples.iter().enumerate() {
+                assert!(sample.is_finite(), "case {case} at {n}: {sample}, {p:?}");
+            }
+
+            // Normal input afterwards stays finite as well.
+            for n in 0..(sample_rate * 0.1) as usize {
+                let mut sample = 0.5 * (n as f32 * 0.05).sin();
+                algo.process_samples(&mut sample, p, None).unwrap();
+                assert!(sample.is_finite(), "case {case} after the input: {p:?}");
+            }
+            let envelope = algo.get_envelope();
+            assert!(
+                envelope.is_finite() && envelope >= 0.0,
+                "case {case}: {envelope}, {p:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn recovers_from_a_nan_burst() {
+        for mode in [
+            DynamicsMode::Compressor,
+            DynamicsMode::UpwardCompressor,
+            DynamicsMode::Expander,
+            DynamicsMode::Gate,
+            DynamicsMode::Limiter,
+        ] {
+            for model in [AnalogModel::Vca, AnalogModel::Fet, AnalogModel::Opto] {
+                let p = RawParameters {
+                    mode,
+                    model,
+                    lookahead: 0.005,
+                    hold: 0.01,
+                    ..parameters()
+                };
+
+                // Half a second of a sine after `burst`.
+                let run = |burst: &[f32]| {
+                    let mut algo = Algo::new(48000.0);
+                    for sample in burst {
+                        let mut sample = *sample;
+                        algo.process_samples(&mut sample, p, None).unwrap();
+                        assert!(sample.is_finite(), "{mode:?}, {model:?}");
+                    }
+
+                    (0..24000)
+                        .map(|n| {
+                            let mut sample = 0.5 * (n as f32 * 0.05).sin();
+                            algo.process_samples(&mut sample, p, None).unwrap();
+                            sample
+                        })
+                        .collect::<Vec<_>>()
+                };
+
+                // Once the burst has left the state the output is the same as without it.
+                let clean = run(&[]);
+                let recovered = run(&[f32::NAN, f32::INFINITY, f32::NEG_INFINITY, f32::NAN]);
+                for (n, (a, b)) in clean.iter().zip(&recovered).enumerate().skip(12000) {
+                    assert!((a - b).abs() < 1e-3, "{mode:?}, {model:?} at {n}: {a}, {b}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fet_recovers_from_huge_input() {
+        let p = RawParameters {
+            model: AnalogModel::Fet,
+            detector: DetectorMode::Rms,
+            ..parameters()
+        };
+
+        let run = |burst: &[f32]| {
+            let mut algo = Algo::new(48000.0);
+            for sample in burst {
+                let mut sample = *sample;
+                algo.process_samples(&mut sample, p, None).unwrap();
+            }
+
+            (0..24000)
+                .map(|n| {
+                    let mut sample = 0.5 * (n as f32 * 0.05).sin();
+                    algo.process_samples(&mut sample, p, None).unwrap();
+                    sample
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // The harmonics of the burst must not linger in the feedback path and keep overflowing
+        // the detector.
+        let clean = run(&[]);
+        let recovered = run(&[f32::MAX, -f32::MAX, 1e30]);
+        for (n, (a, b)) in clean.iter().zip(&recovered).enumerate().skip(12000) {
+            assert!((a - b).abs() < 1e-3, "at {n}: {a}, {b}");
+        }
+    }
 }
@@ -0,0 +1,240 @@
+//! Linkwitz-Riley crossovers that split the signal into bands for multiband compression.
+
+use std::array;
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use crate::biquad::{Biquad, Coefficients, FilterType};
+
+/// Most bands the signal can be split into.
+pub const MAX_BANDS: usize = 4;
+
+/// Fourth order Linkwitz-Riley split: two Butterworth sections per output. Both outputs have the
+/// same phase at every frequency and sum to a second order all-pass.
+struct LinkwitzRiley {
+    low: [Biquad; 2],
+    high: [Biquad; 2],
+}
+
+impl LinkwitzRiley {
+    fn new() -> Self {
+        Self {
+            low: array::from_fn(|_| Biquad::new(Coefficients::IDENTITY)),
+            high: array::from_fn(|_| Biquad::new(Coefficients::IDENTITY)),
+        }
+    }
+
+    fn set_frequency(&mut self, sample_rate: f32, frequency: f32) {
+        let low = Coefficients::new(
+            FilterType::LowPass,
+            sample_rate,
+            frequency,
+            FRAC_1_SQRT_2,
+            0.0,
+        );
+        let high = Coefficients::new(
+            FilterType::HighPass,
+            sample_rate,
+            frequency,
+            FRAC_1_SQRT_2,
+            0.0,
+        );
+
+        self.low
+            .iter_mut()
+            .for_each(|filter| filter.set_coefficients(low));
+        self.high
+            .iter_mut()
+            .for_each(|filter| filter.set_coefficients(high));
+    }
+
+    /// Returns the low and the high part of `sample`.
+    fn process(&mut self, sample: f32) -> (f32, f32) {
+        let low = self
+            .low
+            .iter_mut()
+            .fold(sample, |sample, filter| filter.process(sample));
+        let high = self
+            .high
+            .iter_mut()
+            .fold(sample, |sample, filter| filter.process(sample));
+
+        (low, high)
+    }
+
+    fn reset(&mut self) {
+        self.low
+            .iter_mut()
+            .chain(&mut self.high)
+            .for_each(Biquad::reset);
+    }
+}
+
+/// Splits one channel into up to [`MAX_BANDS`] bands that sum back to an all-pass of the input.
+pub struct Crossover {
+    sample_rate: f32,
+    /// Frequencies the filters were computed for, in Hz.
+    frequencies: [f32; MAX_BANDS - 1],
+    /// The signal above every split is split again by the next one.
+    splits: [LinkwitzRiley; MAX_BANDS - 1],
+    /// `all_passes[band][split]` gives `band` the phase shift of a higher `split` it does not pass
+    /// through, so all bands are in phase when they are summed.
+    all_passes: [[Biquad; MAX_BANDS - 1]; MAX_BANDS - 1],
+}
+
+impl Crossover {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            sample_rate,
+            // Not a valid frequency, so the first split computes the coefficients.
+            frequencies: [0.0; MAX_BANDS - 1],
+            splits: array::from_fn(|_| LinkwitzRiley::new()),
+            all_passes: array::from_fn(|_| array::from_fn(|_| Biquad::new(Coefficients::IDENTITY))),
+        }
+    }
+
+    /// Changes the sample rate and forgets all history.
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.frequencies = [0.0; MAX_BANDS - 1];
+        self.reset();
+    }
+
+    /// Splits `sample` at the lowest `band_count - 1` of `frequencies`. Bands above `band_count`
+    /// are zero, a single band is the unfiltered sample.
+    pub fn split(
+        &mut self,
+        sample: f32,
+        frequencies: [f32; MAX_BANDS - 1],
+        band_count: usize,
+    ) -> [f32; MAX_BANDS] {
+        let mut bands = [0.0; MAX_BANDS];
+        let split_count = band_count.clamp(1, MAX_BANDS) - 1;
+
+        if split_count == 0 {
+            bands[0] = sample;
+            return bands;
+        }
+
+        self.set_frequencies(frequencies);
+
+        let mut rest = sample;
+        for (band, split) in bands.iter_mut().zip(&mut self.splits[..split_count]) {
+            let (low, high) = split.process(rest);
+            *band = low;
+            rest = high;
+        }
+        bands[split_count] = rest;
+
+        for (index, (band, all_passes)) in bands.iter_mut().zip(&mut self.all_passes).enumerate() {
+            for all_pass in all_passes.iter_mut().take(split_count).skip(index + 1) {
+                *band = all_pass.process(*band);
+            }
+        }
+
+        bands
+    }
+
+    /// Recomputes the filters if `frequencies` changed. Every frequency is kept at or above the
+    /// one before it, so the bands never overlap.
+    fn set_frequencies(&mut self, mut frequencies: [f32; MAX_BANDS - 1]) {
+        let mut lowest = 0.0;
+        for frequency in frequencies.iter_mut() {
+            *frequency = frequency.max(lowest);
+            lowest = *frequency;
+        }
+
+        if frequencies == self.frequencies {
+            return;
+        }
+
+        for ((split, frequency), index) in self.splits.iter_mut().zip(frequencies).zip(0..) {
+            split.set_frequency(self.sample_rate, frequency);
+
+            let all_pass = Coefficients::all_pass(self.sample_rate, frequency, FRAC_1_SQRT_2);
+            for all_passes in self.all_passes[..index].iter_mut() {
+                all_passes[index].set_coefficients(all_pass);
+            }
+        }
+
+        self.frequencies = frequencies;
+    }
+
+    pub fn reset(&mut self) {
+        self.splits.iter_mut().for_each(LinkwitzRiley::reset);
+        self.all_passes.iter_mut().flatten().for_each(Biquad::reset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nih_plug::prelude::util;
+
+    use super::*;
+
+    /// Gain in dB of the summed bands for a sine at `frequency` Hz once the filters settled.
+    fn sum_db(
+        sample_rate: f32,
+        frequencies: [f32; MAX_BANDS - 1],
+        band_count: usize,
+        frequency: f32,
+    ) -> f32 {
+        let mut crossover = Crossover::new(sample_rate);
+        // The phase is computed in f64, a long f32 sine would drift at these rates.
+        let sine = |n: usize| {
+            (2.0 * std::f64::consts::PI * (frequency as f64) * n as f64 / sample_rate as f64).sin()
+                as f32
+        };
+
+        let settle = (0.2 * sample_rate) as usize;
+        for n in 0..settle {
+            crossover.split(sine(n), frequencies, band_count);
+        }
+
+        // A whole number of periods, at least a tenth of a second long.
+        let periods = (0.1 * frequency).ceil();
+        let length = (periods * sample_rate / frequency).round() as usize;
+        let energy: f64 = (settle..settle + length)
+            .map(|n| {
+                let bands = crossover.split(sine(n), frequencies, band_count);
+                (bands.iter().sum::<f32>() as f64).powi(2)
+            })
+            .sum();
+
+        util::gain_to_db((2.0 * energy / length as f64).sqrt() as f32)
+    }
+
+    #[test]
+    fn bands_sum_flat() {
+        // Sample rate, crossover frequencies and the frequencies the sum is measured at. The
+        // crossovers run inside the oversampler, up to eight times the host sample rate.
+        let cases = [
+            (
+                48000.0,
+                [200.0, 2000.0, 8000.0],
+                &[50.0, 200.0, 700.0, 2000.0, 5000.0, 8000.0, 15000.0][..],
+            ),
+            (
+                48000.0 * 8.0,
+                [20.0, 150.0, 6000.0],
+                &[20.0, 37.0, 80.0, 1000.0][..],
+            ),
+            (
+                192000.0 * 8.0,
+                [20.0, 150.0, 6000.0],
+                &[20.0, 37.0, 80.0, 1000.0, 15000.0][..],
+            ),
+        ];
+
+        for (sample_rate, frequencies, tested) in cases {
+            for band_count in 2..=MAX_BANDS {
+                for &frequency in tested {
+                    let db = sum_db(sample_rate, frequencies, band_count, frequency);
+                    assert!(
+                        db.abs() < 0.01,
+                        "{band_count} bands at {sample_rate} Hz, {frequency} Hz: {db} dB"
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//! Delay line for the audio path, preallocated so it can be used on the audio thread.
+
+pub struct DelayLine {
+    buffer: Vec<f32>,
+    position: usize,
+}
+
+impl DelayLine {
+    /// Allocates room for delays of up to `max_delay` samples.
+    pub fn new(max_delay: usize) -> Self {
+        Self {
+            buffer: vec![0.0; max_delay + 2],
+            position: 0,
+        }
+    }
+
+    /// Makes room for delays of up to `max_delay` samples and clears the line. Only allocates when
+    /// the line is too short.
+    pub fn set_max_delay(&mut self, max_delay: usize) {
+        if max_delay + 2 > self.buffer.len() {
+            self.buffer = vec![0.0; max_delay + 2];
+        }
+
+        self.reset();
+    }
+
+    /// Writes `sample` and returns the sample from `delay` samples ago. Fractional delays are
+    /// linearly interpolated, so the delay can glide without clicks.
+    pub fn process(&mut self, sample: f32, delay: f32) -> f32 {
+        let length = self.buffer.len();
+        self.buffer[self.position] = sample;
+
+        let delay = delay.clamp(0.0, (length - 2) as f32);
+        let whole = delay as usize;
+        let fraction = delay - whole as f32;
+
+        let newer = self.buffer[(self.position + length - whole) % length];
+        let older = self.buffer[(self.position + length - whole - 1) % length];
+
+        self.position = (self.position + 1) % length;
+
+        newer + (older - newer) * fraction
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer.fill(0.0);
+        self.position = 0;
+    }
+}
@@ -0,0 +1,223 @@
+//! Level detectors that feed the envelope follower in [`crate::compressor::Algo`].
+
+use std::f32::consts::PI;
+
+use nih_plug::prelude::*;
+
+use crate::moving_sum::MovingSum;
+
+/// Longest RMS window that can be selected, in seconds. The RMS buffer is sized for this.
+pub const MAX_RMS_WINDOW: f32 = 0.3;
+
+/// Oversampling factor of the true-peak detector.
+const TRUE_PEAK_PHASES: usize = 8;
+/// Taps per polyphase branch of the true-peak interpolator.
+const TRUE_PEAK_TAPS: usize = 12;
+/// Delay of the true-peak detector in samples, the interpolator looks this far ahead.
+pub const TRUE_PEAK_LATENCY: usize = TRUE_PEAK_TAPS / 2 - 1;
+
+/// Lowest ratio between the level of the true-peak detector and the real peak between two of its
+/// interpolated points, reached by a sine at the Nyquist frequency that peaks halfway between
+/// them. The limiter aims this far below its ceiling.
+pub fn true_peak_margin() -> f32 {
+    (PI / (2.0 * TRUE_PEAK_PHASES as f32)).cos()
+}
+
+/// Measures the level of a signal one sample at a time.
+pub trait Detector {
+    /// Takes the next sample and returns the detected level, which is never negative.
+    fn process(&mut self, sample: f32) -> f32;
+
+    /// Forgets all history.
+    fn reset(&mut self);
+}
+
+/// Detector to use for the envelope follower.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DetectorMode {
+    /// Absolute value of every sample.
+    #[id = "peak"]
+    Peak,
+    /// Root mean square over a sliding window.
+    #[id = "rms"]
+    #[name = "RMS"]
+    Rms,
+    /// Peak of the signal reconstructed at four times the sample rate, so peaks between samples
+    /// are caught as well.
+    #[id = "true-peak"]
+    #[name = "True peak"]
+    TruePeak,
+}
+
+pub struct PeakDetector;
+
+impl Detector for PeakDetector {
+    fn process(&mut self, sample: f32) -> f32 {
+        sample.abs()
+    }
+
+    fn reset(&mut self) {}
+}
+
+pub struct RmsDetector {
+    /// Squared samples of the window.
+    squares: MovingSum,
+    sample_rate: f32,
+}
+
+impl RmsDetector {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            squares: MovingSum::new(rms_capacity(sample_rate), 0.0),
+            sample_rate,
+        }
+    }
+
+    /// Changes the sample rate and forgets all history. The buffer only grows, so going back to a
+    /// lower rate does not allocate.
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.squares.set_capacity(rms_capacity(sample_rate));
+        self.sample_rate = sample_rate;
+    }
+
+    /// Sets the window length in seconds, clamped to [`MAX_RMS_WINDOW`].
+    pub fn set_window(&mut self, window: f32) {
+        self.squares
+            .set_window((window * self.sample_rate).round() as usize);
+    }
+}
+
+/// Squares the RMS buffer needs to hold [`MAX_RMS_WINDOW`] at `sample_rate`.
+fn rms_capacity(sample_rate: f32) -> usize {
+    ((sample_rate * MAX_RMS_WINDOW).ceil() as usize).max(1)
+}
+
+impl Detector for RmsDetector {
+    fn process(&mut self, sample: f32) -> f32 {
+        self.squares.push(sample * sample);
+
+        self.squares.mean().max(0.0).sqrt() as f32
+    }
+
+    fn reset(&mut self) {
+        self.squares.reset();
+    }
+}
+
+pub struct TruePeakDetector {
+    /// Windowed sinc interpolation filter, one branch per intersample position.
+    coefficients: [[f32; TRUE_PEAK_TAPS]; TRUE_PEAK_PHASES],
+    /// Most recent input samples, `history[position]` is the newest.
+    history: [f32; TRUE_PEAK_TAPS],
+    position: usize,
+}
+
+impl TruePeakDetector {
+    pub fn new() -> Self {
+        let mut coefficients = [[0.0; TRUE_PEAK_TAPS]; TRUE_PEAK_PHASES];
+        let half_width = TRUE_PEAK_TAPS as f32 / 2.0 + 1.0;
+
+        for (phase, taps) in coefficients.iter_mut().enumerate() {
+            for (age, tap) in taps.iter_mut().enumerate() {
+                let t =
+                    age as f32 - TRUE_PEAK_LATENCY as f32 + phase as f32 / TRUE_PEAK_PHASES as f32;
+                let sinc = if t == 0.0 {
+                    1.0
+                } else {
+                    (PI * t).sin() / (PI * t)
+                };
+                let window = 0.5 * (1.0 + (PI * t / half_width).cos());
+
+                *tap = sinc * window;
+            }
+
+            let sum: f32 = taps.iter().sum();
+            taps.iter_mut().for_each(|tap| *tap /= sum);
+        }
+
+        Self {
+            coefficients,
+            history: [0.0; TRUE_PEAK_TAPS],
+            position: 0,
+        }
+    }
+}
+
+impl Detector for TruePeakDetector {
+    fn process(&mut self, sample: f32) -> f32 {
+        self.position = (self.position + 1) % TRUE_PEAK_TAPS;
+        self.history[self.position] = sample;
+
+        self.coefficients
+            .iter()
+            .map(|taps| {
+                taps.iter()
+                    .enumerate()
+                    .map(|(age, tap)| {
+                        tap * self.history[(self.position + TRUE_PEAK_TAPS - age) % TRUE_PEAK_TAPS]
+                    })
+                    .sum::<f32>()
+                    .abs()
+            })
+            .fold(0.0, f32::max)
+    }
+
+    fn reset(&mut self) {
+        self.history = [0.0; TRUE_PEAK_TAPS];
+        self.position = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rms_of_a_sine_is_its_amplitude_over_root_two() {
+        let sample_rate = 48000.0;
+        let mut rms = RmsDetector::new(sample_rate);
+        rms.set_window(0.1);
+
+        let mut level = 0.0;
+        for n in 0..sample_rate as usize {
+            level = rms.process((2.0 * PI * 1000.0 * n as f32 / sample_rate).sin());
+        }
+
+        assert!((level - 0.5f32.sqrt()).abs() < 1e-3, "{level}");
+    }
+
+    #[test]
+    fn true_peak_catches_peaks_between_samples() {
+        let mut true_peak = TruePeakDetector::new();
+        let mut peak = PeakDetector;
+
+        // A quarter of the sample rate shifted by 45 degrees never samples its peak.
+        let (mut true_level, mut sampled_level) = (0.0f32, 0.0f32);
+        for n in 0..1000 {
+            let sample = (PI / 2.0 * n as f32 + PI / 4.0).sin();
+            true_level = true_level.max(true_peak.process(sample));
+            sampled_level = sampled_level.max(peak.process(sample));
+        }
+
+        assert!(
+            (sampled_level - 0.5f32.sqrt()).abs() < 1e-3,
+            "{sampled_level}"
+        );
+        assert!((true_level - 1.0).abs() < 0.02, "{true_level}");
+    }
+
+    #[test]
+    fn true_peak_lags_by_its_latency() {
+        let mut true_peak = TruePeakDetector::new();
+
+        let levels: Vec<f32> = (0..TRUE_PEAK_TAPS * 2)
+            .map(|n| true_peak.process(if n == 0 { 1.0 } else { 0.0 }))
+            .collect();
+        let loudest = (0..levels.len())
+            .max_by(|&a, &b| levels[a].total_cmp(&levels[b]))
+            .unwrap();
+
+        assert_eq!(loudest, TRUE_PEAK_LATENCY);
+        assert!((levels[loudest] - 1.0).abs() < 1e-6);
+    }
+}
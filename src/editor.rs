@@ -1,4 +1,5 @@
-use nih_plug::prelude::Editor;
+use atomic_float::AtomicF32;
+use nih_plug::prelude::{util, Editor};
 use nih_plug_vizia::assets;
 use nih_plug_vizia::vizia::prelude::*;
 use nih_plug_vizia::{create_vizia_editor, ViziaState, ViziaTheming};
@@ -10,8 +11,10 @@ mod scopes;
 use std::cell::RefCell;
 use std::f32::consts::PI;
 use std::rc::Rc;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
+use crate::compressor;
 use crate::editor::scopes::{SineScope, TimeConstantsScope};
 use crate::CompressorParams;
 
@@ -32,6 +35,7 @@ pub(crate) fn default_state() -> Arc<ViziaState> {
 
 pub(crate) fn create(
     params: Arc<CompressorParams>,
+    sample_rate: Arc<AtomicF32>,
     editor_state: Arc<ViziaState>,
 ) -> Option<Box<dyn Editor>> {
     create_vizia_editor(
@@ -86,6 +90,7 @@ pub(crate) fn create(
                         cx,
                         SineScope::new(
                             Arc::clone(&params),
+                            Arc::clone(&sample_rate),
                             Box::new(|width| {
                                 (0..width)
                                     .map(|i| (i as f32 / (width as f32 / (2.0 * PI * 1.0))).sin())
@@ -101,6 +106,7 @@ pub(crate) fn create(
                         cx,
                         TimeConstantsScope::new(
                             Arc::clone(&params),
+                            Arc::clone(&sample_rate),
                             Box::new(|width| {
                                 let mut samples = Vec::with_capacity(width);
                                 samples.extend(vec![0.0; width / 8]);
@@ -153,6 +159,23 @@ pub(crate) fn create(
                         Rc::clone(&scope_listeners),
                         true,
                     );
+                    let sample_rate = Arc::clone(&sample_rate);
+                    Label::new(
+                        cx,
+                        Data::params.map(move |params| {
+                            let p = params.current_parameters();
+
+                            if p.auto_makeup {
+                                let sample_rate = sample_rate.load(Ordering::Relaxed);
+                                let prepared = compressor::Prepared::new(&p, sample_rate);
+                                let makeup = util::gain_to_db(prepared.makeup_gain());
+                                format!("Makeup {makeup:+.1} dB")
+                            } else {
+                                String::new()
+                            }
+                        }),
+                    )
+                    .class("makeup_label");
                 });
             })
             .class("main");
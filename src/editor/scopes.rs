@@ -1,10 +1,11 @@
-use std::{sync::Arc, time::{Instant, Duration}};
+use std::{sync::{atomic::Ordering, Arc}, time::{Instant, Duration}};
 
+use atomic_float::AtomicF32;
 use nih_plug_vizia::vizia::vg::Color;
 use vizia_scope::{ScopeData, ScopeLine, ConstantLine, SignalLine, AudioLine};
 
 use crate::{
-    compressor::{self, RawParameters},
+    compressor::{self, DynamicsMode, RawParameters},
     CompressorParams,
 };
 
@@ -17,10 +18,17 @@ macro_rules! to_color {
 const SIGNAL_COLOR: Color = to_color!(243, 250, 146);
 const THRESHOLD_COLOR: Color = to_color!(163, 144, 95);
 const ENVELOPE_COLOR: Color = to_color!(255, 137, 137);
+const HOLD_COLOR: Color = to_color!(137, 196, 255);
+
+/// Heights of the lane at the bottom of the time constants scope that shows when the envelope is
+/// held.
+const HOLD_LANE_IDLE: f32 = -0.98;
+const HOLD_LANE_HOLDING: f32 = -0.9;
 
 
 pub struct SineScope {
     params: Arc<CompressorParams>,
+    sample_rate: Arc<AtomicF32>,
     algo: compressor::Algo,
     width: usize,
     samples: Vec<f32>,
@@ -30,12 +38,14 @@ pub struct SineScope {
 impl SineScope {
     pub fn new(
         parameters: Arc<CompressorParams>,
+        sample_rate: Arc<AtomicF32>,
         base_waveform: Box<dyn Fn(usize) -> Vec<f32>>,
         width: usize,
     ) -> Self {
         let mut scope = Self {
             params: parameters,
-            algo: compressor::Algo::new(),
+            algo: compressor::Algo::new(sample_rate.load(Ordering::Relaxed)),
+            sample_rate,
             width,
             samples: vec![0.0; width],
             base_waveform,
@@ -51,22 +61,30 @@ impl SineScope {
 
 impl ScopeData for SineScope {
     fn recalculate(&mut self) {
+        self.algo
+            .set_sample_rate(self.sample_rate.load(Ordering::Relaxed));
+
         self.samples = (self.base_waveform)(self.width);
 
+        // The compressor is shown with the envelope resting on the peak. The other modes act
+        // below the threshold, so there the envelope follows every sample to show them on the
+        // quiet parts of the sine.
+        let current = self.params.current_parameters();
+        let release = match current.mode {
+            DynamicsMode::Compressor | DynamicsMode::Limiter => 10000.0,
+            DynamicsMode::UpwardCompressor | DynamicsMode::Expander | DynamicsMode::Gate => 0.0,
+        };
+        let parameters = RawParameters {
+            attack: 0.0,
+            release,
+            hold: 0.0,
+            lookahead: 0.0,
+            ..current
+        };
+
         self.samples.iter_mut().for_each(|sample| {
             self.algo
-                .process_samples(
-                    sample,
-                    RawParameters {
-                        threshold: self.params.threshold.value(),
-                        ratio: self.params.ratio.value(),
-                        steepness: self.params.steepness.value(),
-                        attack: 0.0,
-                        release: 10000.0,
-                        gain: self.params.gain.value(),
-                    },
-                    None,
-                )
+                .process_samples(sample, parameters, None)
                 .expect("expect no debugging features to be enabled, so no errors either.");
         });
     }
@@ -92,10 +110,12 @@ impl ScopeData for SineScope {
 
 pub struct TimeConstantsScope {
     params: Arc<CompressorParams>,
+    sample_rate: Arc<AtomicF32>,
     algo: compressor::Algo,
     amount_of_samples: usize,
     samples: Vec<f32>,
     envelope: Vec<f32>,
+    hold: Vec<f32>,
     base_waveform: Box<dyn Fn(usize) -> Vec<f32>>,
     last_recalc: Instant,
 }
@@ -105,15 +125,18 @@ const DEBOUNCE_TIME_MS: u64 = 33;
 impl TimeConstantsScope {
     pub fn new(
         parameters: Arc<CompressorParams>,
+        sample_rate: Arc<AtomicF32>,
         base_waveform: Box<dyn Fn(usize) -> Vec<f32>>,
         amount_of_samples: usize,
     ) -> Self {
         Self {
             params: parameters,
-            algo: compressor::Algo::new(),
+            algo: compressor::Algo::new(sample_rate.load(Ordering::Relaxed)),
+            sample_rate,
             amount_of_samples,
             samples: Vec::with_capacity(amount_of_samples),
             envelope: Vec::with_capacity(amount_of_samples),
+            hold: Vec::with_capacity(amount_of_samples),
             base_waveform,
             last_recalc: Instant::now() - Duration::from_millis(DEBOUNCE_TIME_MS),
         }
@@ -130,26 +153,25 @@ impl ScopeData for TimeConstantsScope {
         
         self.samples = (self.base_waveform)(self.amount_of_samples);
         self.envelope = Vec::with_capacity(self.amount_of_samples);
+        self.hold = Vec::with_capacity(self.amount_of_samples);
 
         self.algo.reset();
+        self.algo
+            .set_sample_rate(self.sample_rate.load(Ordering::Relaxed));
+
+        let parameters = self.params.current_parameters();
 
         self.samples.iter_mut().for_each(|sample| {
             self.algo
-                .process_samples(
-                    sample,
-                    RawParameters {
-                        threshold: self.params.threshold.value(),
-                        ratio: self.params.ratio.value(),
-                        steepness: self.params.steepness.value(),
-                        attack: self.params.attack.value() / 1000.0,
-                        release: self.params.release.value() / 1000.0,
-                        gain: self.params.gain.value(),
-                    },
-                    None
-                )
+                .process_samples(sample, parameters, None)
                 .expect("expect no debugging features to be enabled, so no errors either.");
 
             self.envelope.push(-self.algo.get_envelope());
+            self.hold.push(if self.algo.is_holding() {
+                HOLD_LANE_HOLDING
+            } else {
+                HOLD_LANE_IDLE
+            });
         });
 
         self.last_recalc = now;
@@ -174,6 +196,7 @@ impl ScopeData for TimeConstantsScope {
                 ENVELOPE_COLOR,
                 1.5,
             )),
+            ScopeLine::Signal(SignalLine::new(&self.hold, HOLD_COLOR, 1.0)),
         ]
     }
 }
@@ -1,25 +1,101 @@
-//! VST3 plugin implementing an audio compressor with six parameters:
+//! VST3 plugin implementing an audio compressor with the following parameters:
 //! * Threshold: Level above which to start compressing.
 //! * Ratio: Amount of compression
 //! * Steepness: measure of quickly the compression engages. A low steepness means that as the actual audio
 //!     level approaches the threshold the compressor will gradually already engage. A high steepness will be
 //!     more like the compressor engaging not at all under the threshold and immediately after it goes over
 //!     the threshold. It is a measure of how smooth the knee is.
+//! * Knee: steepness uses the sigmoid above, width uses a quadratic knee in dB with a width of
+//!     Knee Width dB around the threshold that does not change with the threshold.
+//! * Mode: compressor turns the signal above the threshold down. Upward compressor raises the
+//!     signal below the threshold by the ratio, by at most Range dB, and leaves the signal above it
+//!     alone. Expander turns the signal below the threshold further down by the ratio and gate
+//!     turns it down completely, both by at most Range dB. The gate only closes once the level
+//!     falls Hysteresis dB below the threshold. Limiter keeps the true peak of the output below
+//!     Ceiling dBTP, reaching the needed gain within the lookahead and releasing with the release
+//!     time. It aims 0.17 dB below the ceiling, the most its detector can miss between samples.
+//!     Gain drives the limiter, makeup, mix, the harmonics of the models and the external
+//!     sidechain are not used. The limiter runs on the left and right output at the host sample
+//!     rate, so Bands, Channel Mode and Oversampling do not apply to it. All modes but the
+//!     compressor use the clean architecture.
+//! * Model: VCA is the compressor as set. FET uses the feedback topology with a very fast attack
+//!     and adds harmonics that grow with the ratio and the gain reduction. Opto attacks faster on
+//!     big overs and releases slower the longer it has been compressing, like a light-dependent
+//!     resistor.
 //! * Attack: Time in ms until the compressor fully engages.
 //! * Release: Time in ms until the compressor is fully disengaged.
+//! * Hold: Time in ms the envelope stays at its peak after the level falls before the release
+//!     starts. Keeps the gain from fluttering on low frequencies.
 //! * Gain: gain to apply after compression.
+//! * Auto Makeup: adds the gain that brings a full scale signal back to full scale after the
+//!     compression, on top of Gain. The editor shows the amount next to the Gain knob.
+//! * Lookahead: delays the audio by up to 10 ms while the detector listens to the undelayed signal,
+//!     so the compressor already reacts before a transient arrives. The delay is reported to the
+//!     host as latency.
+//! * Mix: blends the dry input with the compressed output for parallel compression.
+//! * Ballistics: curve of the envelope during attack and release. Linear reaches the level in the
+//!     attack/release time, exponential covers 63% of the distance in that time and log release
+//!     releases at a constant rate in dB. Auto release releases quickly after short peaks and
+//!     slowly after sustained compression.
+//! * Architecture: character bends the waveform around the threshold and adds harmonics, clean
+//!     multiplies the signal with a gain computed from the envelope.
+//! * Oversampling: runs the compression at 2, 4 or 8 times the sample rate so the harmonics of
+//!     the character architecture do not alias. Adds latency that is reported to the host.
+//! * Topology: feed-forward detects the input, feedback detects the compressed output which gives
+//!     a softer effective ratio.
+//! * Detector: level the envelope follows. Peak uses every sample's absolute value, RMS the root
+//!     mean square over RMS Window ms and true peak also catches peaks between samples, which
+//!     adds a few samples of latency.
+//! * Stereo Link: 0% compresses every channel on its own, 100% drives all channels with the
+//!     combined level from Link Mode (loudest channel or average) so the stereo image stays put.
+//!     Linking blends the gain reduction of the combined level into every channel on top of its
+//!     own, so the architecture stays as set.
+//! * Channel Mode: stereo compresses left and right, mid only and side only compress one half of
+//!     the mid/side encoded signal and M/S compresses both, the side channel with the Side settings.
+//!     Linking only applies in stereo mode and mono input is always processed as stereo.
+//! * Sidechain: internal lets the detectors listen to the main input, external to the sidechain
+//!     input. Nothing is compressed while nothing is routed to the sidechain input, the host then
+//!     passes silence. Sidechain Listen outputs the signal the detectors listen to instead of the
+//!     compressed signal. With the feedback topology the detectors listen to the output, Sidechain
+//!     Listen still outputs the filtered input or sidechain signal.
+//! * Sidechain Filter: high-pass, low-pass, band-pass or shelf filter at Sidechain Frequency with
+//!     Sidechain Q (and Sidechain Gain for the shelves) applied to what the detectors listen to,
+//!     e.g. to stop bass notes from pumping the compressor.
+//! * Bands: splits the signal into up to four bands at the Low, Mid and High Crossover
+//!     frequencies with Linkwitz-Riley filters. Every band is compressed with its own Band
+//!     settings instead of the main and Side ones, and the bands sum back to a flat response.
 
 extern crate csv;
 
-use compressor::Algo;
+use atomic_float::AtomicF32;
+use biquad::FilterType;
+use channels::{ChannelMode, LinkMode, SidechainSource, MAX_CHANNELS};
+use compressor::{
+    Algo, Architecture, Ballistics, DynamicsMode, Knee, RawParameters, Topology, MAX_HOLD,
+};
+use crossover::{Crossover, MAX_BANDS};
+use detector::DetectorMode;
 #[cfg(feature = "detailed_debugging")]
 use llad::SampleLogger;
+use model::AnalogModel;
 use nih_plug::prelude::*;
+use nih_plug::wrapper::state::{ParamValue, PluginState};
 use nih_plug_vizia::ViziaState;
+use oversampling::{Oversampler, Oversampling, MAX_OVERSAMPLING};
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
+mod biquad;
+mod channels;
 mod compressor;
+mod crossover;
+mod delay;
+mod detector;
 mod editor;
+mod limiter;
+mod model;
+mod moving_sum;
+mod oversampling;
 
 /// Parameters for the compressor.
 #[derive(Params, Debug)]
@@ -35,20 +111,128 @@ pub struct CompressorParams {
     pub attack: FloatParam, // [0, inf), milliseconds
     #[id = "release"]
     pub release: FloatParam, // [0, inf), milliseconds
+    #[id = "hold"]
+    pub hold: FloatParam, // [0, MAX_HOLD], milliseconds
     #[id = "steepness"]
     pub steepness: FloatParam, // [0, inf)
+    #[id = "knee"]
+    pub knee: EnumParam<Knee>,
+    #[id = "knee_width"]
+    pub knee_width: FloatParam, // [0, KNEE_WIDTH_MAX_DB], dB
+    #[id = "mode"]
+    pub mode: EnumParam<DynamicsMode>,
+    #[id = "model"]
+    pub model: EnumParam<AnalogModel>,
+    #[id = "range"]
+    pub range: FloatParam, // [0, 80], dB of attenuation, or of boost when compressing upward
+    #[id = "hysteresis"]
+    pub hysteresis: FloatParam, // [0, 12], dB
+    #[id = "ceiling"]
+    pub ceiling: FloatParam, // stored as gain, entered in dBTP
     #[id = "gain"]
     pub gain: FloatParam, // stored as gain, entered in dB
+    #[id = "auto_makeup"]
+    pub auto_makeup: BoolParam,
+    #[id = "lookahead"]
+    pub lookahead: FloatParam, // [0, 10], milliseconds
+    #[id = "mix"]
+    pub mix: FloatParam, // [0, 1], shown as percentage
+    #[id = "ballistics"]
+    pub ballistics: EnumParam<Ballistics>,
+    #[id = "architecture"]
+    pub architecture: EnumParam<Architecture>,
+    #[id = "oversampling"]
+    pub oversampling: EnumParam<Oversampling>,
+    #[id = "topology"]
+    pub topology: EnumParam<Topology>,
+    #[id = "detector"]
+    pub detector: EnumParam<DetectorMode>,
+    #[id = "rms_window"]
+    pub rms_window: FloatParam, // [1, 300], milliseconds
+    #[id = "stereo_link"]
+    pub stereo_link: FloatParam, // [0, 1], shown as percentage
+    #[id = "link_mode"]
+    pub link_mode: EnumParam<LinkMode>,
+    #[id = "channel_mode"]
+    pub channel_mode: EnumParam<ChannelMode>,
+    #[id = "sidechain"]
+    pub sidechain: EnumParam<SidechainSource>,
+    #[id = "sidechain_listen"]
+    pub sidechain_listen: BoolParam,
+    #[id = "sidechain_filter"]
+    pub sidechain_filter: EnumParam<FilterType>,
+    #[id = "sidechain_frequency"]
+    pub sidechain_frequency: FloatParam, // [20, 20000], Hz
+    #[id = "sidechain_q"]
+    pub sidechain_q: FloatParam,
+    #[id = "sidechain_gain"]
+    pub sidechain_gain: FloatParam, // [-24, 24], dB, only used by the shelves
+
+    /// Settings for the side channel in M/S mode.
+    #[nested(id_prefix = "side", group = "Side")]
+    pub side: DynamicsParams,
+
+    #[id = "band_count"]
+    pub band_count: IntParam, // [1, MAX_BANDS], 1 compresses the whole signal at once
+    #[id = "crossover_low"]
+    pub crossover_low: FloatParam, // [20, 20000], Hz
+    #[id = "crossover_mid"]
+    pub crossover_mid: FloatParam, // [20, 20000], Hz
+    #[id = "crossover_high"]
+    pub crossover_high: FloatParam, // [20, 20000], Hz
+    /// Settings for every band when the signal is split into bands.
+    #[nested(array, group = "Band")]
+    pub bands: [DynamicsParams; MAX_BANDS],
 
     #[cfg(feature = "detailed_debugging")]
     #[id = "logger_length"]
     pub logger_length: FloatParam,
 }
 
+/// Settings of one compressor when the signal is split into parts that are compressed separately.
+#[derive(Params, Debug)]
+pub struct DynamicsParams {
+    #[id = "threshold"]
+    pub threshold: FloatParam,
+    #[id = "ratio"]
+    pub ratio: FloatParam,
+    #[id = "attack"]
+    pub attack: FloatParam,
+    #[id = "release"]
+    pub release: FloatParam,
+    #[id = "gain"]
+    pub gain: FloatParam,
+}
+
+/// Widest knee that can be set with [`Knee::Width`], in dB.
+const KNEE_WIDTH_MAX_DB: f32 = 24.0;
+
+/// Sample rate assumed until the host tells us the real one in `initialize`.
+const DEFAULT_SAMPLE_RATE: f32 = 48000.0;
+
+/// Everything that processes one channel.
+struct Channel {
+    oversampler: Oversampler,
+    key_oversampler: Oversampler,
+    crossover: Crossover,
+    /// Splits the external sidechain, the internal key is split along with the audio.
+    key_crossover: Crossover,
+    /// One compressor per band, only the first is used without band splitting.
+    algos: [Algo; MAX_BANDS],
+}
+
 /// Struct implementing [`nih_plug::prelude::Plugin`].
 pub struct Compressor {
     params: Arc<CompressorParams>,
-    algos: Vec<Algo>,
+    channels: Vec<Channel>,
+    /// Host sample rate, shared with the editor so the scopes preview at the same rate.
+    sample_rate: Arc<AtomicF32>,
+    /// Latency last reported to the host, in samples.
+    latency: u32,
+    /// Seconds added to the lookahead so the audio is delayed by exactly the reported latency.
+    padding: f32,
+    /// Oversampling the channels are currently running at.
+    oversampling: Oversampling,
     #[cfg(feature = "detailed_debugging")]
     logger: SampleLogger,
 }
@@ -58,50 +242,266 @@ impl Compressor {
     fn process_buffer(
         &mut self,
         buffer: &mut Buffer,
-        _aux: &mut AuxiliaryBuffers,
-        _context: &mut impl ProcessContext<Self>,
+        aux: &mut AuxiliaryBuffers,
+        context: &mut impl ProcessContext<Self>,
     ) -> Result<(), &'static str> {
         #[cfg(feature = "detailed_debugging")]
         self.logger
             .set_quit_after_n_samples(self.params.logger_length.value() as u64);
 
-        for channel_samples in buffer.iter_samples() {
-            let threshold = self.params.threshold.smoothed.next();
-            let ratio = self.params.ratio.smoothed.next();
-            let attack = self.params.attack.smoothed.next() / 1000.0;
-            let release = self.params.release.smoothed.next() / 1000.0;
-            let steepness = self.params.steepness.smoothed.next();
-            let gain = self.params.gain.smoothed.next();
+        let oversampling = self.oversampling_setting();
+        if oversampling != self.oversampling {
+            self.set_oversampling(oversampling);
+        }
+
+        let (latency, padding) = self.latency();
+        self.padding = padding;
+        if latency != self.latency {
+            context.set_latency_samples(latency);
+            self.latency = latency;
+        }
+
+        // The limiter has to listen to what it limits to keep its ceiling, so it runs on the
+        // whole output without the external sidechain, bands or mid/side.
+        let limiter = self.params.mode.value() == DynamicsMode::Limiter;
+        // Hosts hand over a silent buffer when the sidechain port exists but nothing is routed
+        // to it, only the layouts without the port leave the main input to the detectors.
+        let sidechain = match self.params.sidechain.value() {
+            SidechainSource::External if !limiter => {
+                aux.inputs.first().map(Buffer::as_slice_immutable)
+            }
+            _ => None,
+        };
+        let listen = self.params.sidechain_listen.value();
+
+        #[cfg(not(feature = "detailed_debugging"))]
+        if sidechain.is_none() && !listen && self.can_process_blocks(buffer.channels()) {
+            let p = self.next_parameters();
+            for (channel, samples) in self.channels.iter_mut().zip(buffer.as_slice()) {
+                channel.algos[0].process_block(samples, &p)?;
+            }
+            return Ok(());
+        }
+
+        for (sample_index, mut channel_samples) in buffer.iter_samples().enumerate() {
+            let p = self.next_parameters();
+            let side = self.params.side.next_parameters(p);
+            let link = self.params.stereo_link.smoothed.next();
+            let link_mode = self.params.link_mode.value();
+            let band_count = if limiter {
+                1
+            } else {
+                self.params.band_count.value() as usize
+            };
+            let frequencies = [
+                self.params.crossover_low.smoothed.next(),
+                self.params.crossover_mid.smoothed.next(),
+                self.params.crossover_high.smoothed.next(),
+            ];
+            // Read every band so their smoothers keep up while they are not in use.
+            let band_parameters = self.params.bands.each_ref().map(|band| {
+                let p = band.next_parameters(p);
+                [p, p]
+            });
 
-            while self.algos.len() < channel_samples.len() {
-                self.algos.push(Algo::new());
+            let num_channels = channel_samples.len();
+            if num_channels > self.channels.len() {
+                return Err("Received more channels than were allocated in initialize.");
             }
 
-            assert!(channel_samples.len() == self.algos.len());
-
-            for (algo_id, sample) in channel_samples.into_iter().enumerate() {
-                self.algos
-                    .get_mut(algo_id)
-                    .unwrap_or_else(|| panic!("Expect algo id {algo_id} to be present."))
-                    .process_samples(                
-                        sample,
-                        compressor::RawParameters {
-                            threshold,
-                            ratio,
-                            steepness,
-                            attack,
-                            release,
-                            gain,
-                        },
-                        #[cfg(feature = "detailed_debugging")]
-                        if algo_id == 0 {
-                            Some(&mut self.logger)
+            // Mid/side needs two channels, mono falls back to plain stereo processing.
+            let channel_mode = if num_channels == MAX_CHANNELS && !limiter {
+                self.params.channel_mode.value()
+            } else {
+                ChannelMode::Stereo
+            };
+
+            // With bands the band settings replace the main and side settings.
+            let parameters = if band_count > 1 {
+                band_parameters
+            } else {
+                match channel_mode {
+                    ChannelMode::MidSide => [[p, side]; MAX_BANDS],
+                    _ => [[p, p]; MAX_BANDS],
+                }
+            };
+            // Linking mid and side would defeat the point of treating them separately.
+            let linked = channel_mode == ChannelMode::Stereo && num_channels > 1 && link > 0.0;
+
+            // The crossovers and the oversamplers would hold on to a NaN from upstream.
+            let mut frame = [0.0; MAX_CHANNELS];
+            for (value, sample) in frame.iter_mut().zip(channel_samples.iter_mut()) {
+                *value = compressor::flush(*sample);
+            }
+
+            let mut key = frame;
+            if let Some(sidechain) = sidechain {
+                for (index, value) in key[..num_channels].iter_mut().enumerate() {
+                    // A mono sidechain drives every channel.
+                    *value = sidechain
+                        .get(index)
+                        .or(sidechain.first())
+                        .map_or(0.0, |channel| compressor::flush(channel[sample_index]));
+                }
+            }
+
+            if channel_mode.is_mid_side() {
+                channels::encode_mid_side(&mut frame);
+                channels::encode_mid_side(&mut key);
+            }
+
+            // The compression runs on `factor` samples per host sample, indexed as
+            // `frames[step][channel]`.
+            let factor = oversampling.factor();
+            let mut frames = [[0.0; MAX_CHANNELS]; MAX_OVERSAMPLING];
+            let mut keys = frames;
+            for (index, channel) in self.channels.iter_mut().take(num_channels).enumerate() {
+                let upsampled = channel.oversampler.upsample(frame[index], oversampling);
+                let upsampled_key = if sidechain.is_some() {
+                    channel.key_oversampler.upsample(key[index], oversampling)
+                } else {
+                    upsampled
+                };
+
+                for ((frame, key), (value, key_value)) in frames
+                    .iter_mut()
+                    .zip(keys.iter_mut())
+                    .zip(upsampled.into_iter().zip(upsampled_key))
+                {
+                    frame[index] = value;
+                    key[index] = key_value;
+                }
+            }
+
+            // Only the first channel of the first band is logged.
+            #[cfg(feature = "detailed_debugging")]
+            let mut logger = Some(&mut self.logger);
+            #[cfg(not(feature = "detailed_debugging"))]
+            let mut logger = None;
+
+            for (frame, key) in frames.iter_mut().zip(&keys).take(factor) {
+                // Indexed as `bands[band][channel]`.
+                let mut bands = [[0.0; MAX_CHANNELS]; MAX_BANDS];
+                let mut key_bands = bands;
+                for (index, ((channel, sample), key_sample)) in self
+                    .channels
+                    .iter_mut()
+                    .zip(*frame)
+                    .zip(*key)
+                    .take(num_channels)
+                    .enumerate()
+                {
+                    let split = channel.crossover.split(sample, frequencies, band_count);
+                    let key_split = if sidechain.is_some() {
+                        channel
+                            .key_crossover
+                            .split(key_sample, frequencies, band_count)
+                    } else {
+                        split
+                    };
+
+                    for ((band, key_band), (value, key_value)) in bands
+                        .iter_mut()
+                        .zip(key_bands.iter_mut())
+                        .zip(split.into_iter().zip(key_split))
+                    {
+                        band[index] = value;
+                        key_band[index] = key_value;
+                    }
+                }
+
+                for (band_index, ((band, key_band), parameters)) in bands
+                    .iter_mut()
+                    .zip(key_bands)
+                    .zip(parameters)
+                    .take(band_count)
+                    .enumerate()
+                {
+                    // Prepared once per channel and shared by the detection and the compression.
+                    let mut prepared = [None; MAX_CHANNELS];
+                    let mut levels = [0.0; MAX_CHANNELS];
+                    for ((((level, c), channel), key), p) in levels
+                        .iter_mut()
+                        .zip(prepared.iter_mut())
+                        .zip(self.channels.iter_mut())
+                        .zip(&key_band[..num_channels])
+                        .zip(&parameters)
+                    {
+                        let algo = &mut channel.algos[band_index];
+                        let prepared = algo.prepare(p);
+                        *level = algo.detect(*key, &prepared);
+                        *c = Some(prepared);
+                    }
+
+                    if listen {
+                        for (value, channel) in
+                            band[..num_channels].iter_mut().zip(self.channels.iter())
+                        {
+                            *value = channel.algos[band_index].key();
+                        }
+                    }
+
+                    // Every channel that is processed was prepared above.
+                    for (index, (((channel, sample), level), c)) in self
+                        .channels
+                        .iter_mut()
+                        .zip(band[..num_channels].iter_mut())
+                        .zip(levels)
+                        .zip(prepared.iter().flatten())
+                        .enumerate()
+                    {
+                        let algo = &mut channel.algos[band_index];
+                        if channel_mode.is_compressed(index) && !listen {
+                            algo.process_with_level(sample, level, c, logger.take())?;
                         } else {
-                            None
-                        },
-                        #[cfg(not(feature = "detailed_debugging"))]
-                        None
-                    )?;
+                            algo.delay(sample, c);
+                        }
+                    }
+
+                    // Linked channels share the same parameters, those of the first channel
+                    // serve all of them.
+                    if let (true, Some(c)) = (linked && !listen, &prepared[0]) {
+                        let mut envelopes = [0.0; MAX_CHANNELS];
+                        for (envelope, channel) in envelopes.iter_mut().zip(self.channels.iter()) {
+                            *envelope = channel.algos[band_index].get_envelope();
+                        }
+
+                        let gains =
+                            channels::link_gains(&envelopes[..num_channels], c, link, link_mode);
+                        for ((sample, channel), gain) in band[..num_channels]
+                            .iter_mut()
+                            .zip(self.channels.iter())
+                            .zip(gains)
+                        {
+                            channel.algos[band_index].apply_link_gain(sample, gain, c);
+                        }
+                    }
+                }
+
+                for (index, value) in frame[..num_channels].iter_mut().enumerate() {
+                    *value = bands[..band_count].iter().map(|band| band[index]).sum();
+                }
+            }
+
+            for (index, (value, channel)) in frame[..num_channels]
+                .iter_mut()
+                .zip(self.channels.iter_mut())
+                .enumerate()
+            {
+                let mut upsampled = [0.0; MAX_OVERSAMPLING];
+                for (sample, frame) in upsampled.iter_mut().zip(&frames) {
+                    *sample = frame[index];
+                }
+
+                *value = channel.oversampler.downsample(upsampled, oversampling);
+            }
+
+            if channel_mode.is_mid_side() {
+                channels::decode_mid_side(&mut frame);
+            }
+
+            for (sample, value) in channel_samples.into_iter().zip(frame) {
+                *sample = value;
             }
         }
 
@@ -109,11 +509,207 @@ impl Compressor {
     }
 }
 
+impl Compressor {
+    /// Latency the lookahead, the true-peak detector and the oversampling add, in whole samples,
+    /// and the seconds the lookahead is lengthened by so the audio is delayed by exactly that
+    /// much. Uses the target value of the lookahead so the host can realign while the delay itself
+    /// glides there.
+    fn latency(&self) -> (u32, f32) {
+        let oversampling = self.oversampling_setting();
+        let sample_rate = self.sample_rate.load(Ordering::Relaxed) * oversampling.factor() as f32;
+        let delay = compressor::delay(&self.params.current_parameters(), sample_rate);
+        let (latency, padding) = oversampling.whole_latency(delay);
+
+        (latency, padding / sample_rate)
+    }
+
+    /// Oversampling the channels should run at. The limiter runs at the host sample rate, the
+    /// oversampling filters would ring over the ceiling it keeps.
+    fn oversampling_setting(&self) -> Oversampling {
+        if self.params.mode.value() == DynamicsMode::Limiter {
+            Oversampling::X1
+        } else {
+            self.params.oversampling.value()
+        }
+    }
+
+    /// Runs every channel at `oversampling` times the host sample rate.
+    fn set_oversampling(&mut self, oversampling: Oversampling) {
+        let sample_rate = self.sample_rate.load(Ordering::Relaxed) * oversampling.factor() as f32;
+
+        for channel in self.channels.iter_mut() {
+            channel.oversampler.reset();
+            channel.key_oversampler.reset();
+            channel.crossover.set_sample_rate(sample_rate);
+            channel.key_crossover.set_sample_rate(sample_rate);
+            for algo in channel.algos.iter_mut() {
+                algo.set_sample_rate(sample_rate);
+            }
+        }
+
+        self.oversampling = oversampling;
+    }
+
+    /// Whether every channel can be compressed on its own as a block with the same parameters.
+    /// That is the case while nothing is smoothing, a single band runs at the host sample rate
+    /// and the channels are neither linked nor mid/side encoded.
+    fn can_process_blocks(&self, num_channels: usize) -> bool {
+        let params = &self.params;
+        let linked = num_channels > 1
+            && (params.stereo_link.value() > 0.0
+                || params.channel_mode.value() != ChannelMode::Stereo);
+
+        num_channels <= self.channels.len()
+            && !linked
+            && params.band_count.value() == 1
+            && self.oversampling == Oversampling::X1
+            && !params.is_smoothing()
+            && compressor::supports_block(&params.current_parameters())
+    }
+
+    /// Reads the next value of every smoothed parameter.
+    fn next_parameters(&self) -> RawParameters {
+        RawParameters {
+            threshold: self.params.threshold.smoothed.next(),
+            ratio: self.params.ratio.smoothed.next(),
+            steepness: self.params.steepness.smoothed.next(),
+            knee: self.params.knee.value(),
+            knee_width: self.params.knee_width.smoothed.next(),
+            mode: self.params.mode.value(),
+            model: self.params.model.value(),
+            range: self.params.range.smoothed.next(),
+            hysteresis: self.params.hysteresis.value(),
+            ceiling: self.params.ceiling.smoothed.next(),
+            attack: self.params.attack.smoothed.next() / 1000.0,
+            release: self.params.release.smoothed.next() / 1000.0,
+            hold: self.params.hold.value() / 1000.0,
+            gain: self.params.gain.smoothed.next(),
+            ballistics: self.params.ballistics.value(),
+            architecture: self.params.architecture.value(),
+            topology: self.params.topology.value(),
+            detector: self.params.detector.value(),
+            rms_window: self.params.rms_window.value() / 1000.0,
+            sidechain_filter: self.params.sidechain_filter.value(),
+            sidechain_frequency: self.params.sidechain_frequency.smoothed.next(),
+            sidechain_q: self.params.sidechain_q.smoothed.next(),
+            sidechain_gain: self.params.sidechain_gain.smoothed.next(),
+            lookahead: self.params.lookahead.smoothed.next() / 1000.0 + self.padding,
+            auto_makeup: self.params.auto_makeup.value(),
+            mix: self.params.mix.smoothed.next(),
+        }
+    }
+}
+
+impl CompressorParams {
+    /// Current value of every parameter without smoothing, for the previews in the editor and the
+    /// latency.
+    pub(crate) fn current_parameters(&self) -> RawParameters {
+        RawParameters {
+            threshold: self.threshold.value(),
+            ratio: self.ratio.value(),
+            steepness: self.steepness.value(),
+            knee: self.knee.value(),
+            knee_width: self.knee_width.value(),
+            mode: self.mode.value(),
+            model: self.model.value(),
+            range: self.range.value(),
+            hysteresis: self.hysteresis.value(),
+            ceiling: self.ceiling.value(),
+            attack: self.attack.value() / 1000.0,
+            release: self.release.value() / 1000.0,
+            hold: self.hold.value() / 1000.0,
+            gain: self.gain.value(),
+            ballistics: self.ballistics.value(),
+            architecture: self.architecture.value(),
+            topology: self.topology.value(),
+            detector: self.detector.value(),
+            rms_window: self.rms_window.value() / 1000.0,
+            sidechain_filter: self.sidechain_filter.value(),
+            sidechain_frequency: self.sidechain_frequency.value(),
+            sidechain_q: self.sidechain_q.value(),
+            sidechain_gain: self.sidechain_gain.value(),
+            lookahead: self.lookahead.value() / 1000.0,
+            auto_makeup: self.auto_makeup.value(),
+            mix: self.mix.value(),
+        }
+    }
+
+    /// Whether any parameter read while processing is still smoothing towards its value.
+    fn is_smoothing(&self) -> bool {
+        [
+            &self.threshold,
+            &self.ratio,
+            &self.steepness,
+            &self.knee_width,
+            &self.range,
+            &self.ceiling,
+            &self.attack,
+            &self.release,
+            &self.gain,
+            &self.sidechain_frequency,
+            &self.sidechain_q,
+            &self.sidechain_gain,
+            &self.lookahead,
+            &self.mix,
+            &self.stereo_link,
+            &self.crossover_low,
+            &self.crossover_mid,
+            &self.crossover_high,
+        ]
+        .iter()
+        .any(|param| param.smoothed.is_smoothing())
+            || self.side.is_smoothing()
+            || self.bands.iter().any(DynamicsParams::is_smoothing)
+    }
+}
+
+impl DynamicsParams {
+    /// Whether any of the parameters is still smoothing towards its value.
+    fn is_smoothing(&self) -> bool {
+        [
+            &self.threshold,
+            &self.ratio,
+            &self.attack,
+            &self.release,
+            &self.gain,
+        ]
+        .iter()
+        .any(|param| param.smoothed.is_smoothing())
+    }
+
+    /// Creates the parameters with `name` in front of every parameter name.
+    fn new(name: &str) -> Self {
+        Self {
+            threshold: threshold_param(&format!("{name} Threshold")),
+            ratio: ratio_param(&format!("{name} Ratio")),
+            attack: attack_param(&format!("{name} Attack")),
+            release: release_param(&format!("{name} Release")),
+            gain: gain_param(&format!("{name} Gain")),
+        }
+    }
+
+    /// Reads the next value of every smoothed parameter, taking the rest from `base`.
+    fn next_parameters(&self, base: RawParameters) -> RawParameters {
+        RawParameters {
+            threshold: self.threshold.smoothed.next(),
+            ratio: self.ratio.smoothed.next(),
+            attack: self.attack.smoothed.next() / 1000.0,
+            release: self.release.smoothed.next() / 1000.0,
+            gain: self.gain.smoothed.next(),
+            ..base
+        }
+    }
+}
+
 impl Default for Compressor {
     fn default() -> Self {
         Self {
             params: Arc::new(CompressorParams::default()),
-            algos: Vec::new(),
+            channels: Vec::new(),
+            sample_rate: Arc::new(AtomicF32::new(DEFAULT_SAMPLE_RATE)),
+            latency: 0,
+            padding: 0.0,
+            oversampling: Oversampling::X1,
             #[cfg(feature = "detailed_debugging")]
             logger: SampleLogger::new(String::from("debug.csv")),
         }
@@ -124,82 +720,206 @@ impl Default for CompressorParams {
     fn default() -> Self {
         Self {
             editor_state: editor::default_state(),
-            threshold: FloatParam::new(
-                "Threshold",
-                util::db_to_gain(-10.0),
+            threshold: threshold_param("Threshold"),
+            ratio: ratio_param("Ratio"),
+            attack: attack_param("Attack"),
+            release: release_param("Release"),
+
+            hold: FloatParam::new(
+                "Hold",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: MAX_HOLD * 1000.0,
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_rounded(0))
+            .with_unit(" ms"),
+
+            steepness: FloatParam::new(
+                "Steepness",
+                8.0,
                 FloatRange::Skewed {
-                    min: util::db_to_gain(-30.0),
-                    max: util::db_to_gain(0.0),
-                    factor: FloatRange::gain_skew_factor(-30.0, 0.0),
+                    min: 1.0,
+                    max: 300.0,
+                    factor: FloatRange::gain_skew_factor(1.0, 40.0),
                 },
             )
-            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+
+            knee: EnumParam::new("Knee", Knee::Steepness),
+
+            knee_width: FloatParam::new(
+                "Knee Width",
+                6.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: KNEE_WIDTH_MAX_DB,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
             .with_unit(" dB")
-            .with_value_to_string(formatters::v2s_f32_gain_to_db(1))
-            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            mode: EnumParam::new("Mode", DynamicsMode::Compressor),
+
+            model: EnumParam::new("Model", AnalogModel::Vca),
+
+            range: FloatParam::new(
+                "Range",
+                40.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 80.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
 
-            ratio: FloatParam::new(
-                "Ratio",
+            hysteresis: FloatParam::new(
+                "Hysteresis",
                 3.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 12.0,
+                },
+            )
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            ceiling: FloatParam::new(
+                "Ceiling",
+                util::db_to_gain(-1.0),
                 FloatRange::Skewed {
-                    min: 1.0,
-                    max: 200.0,
-                    factor: FloatRange::gain_skew_factor(1.0, 40.0),
+                    min: util::db_to_gain(-12.0),
+                    max: util::db_to_gain(0.0),
+                    factor: FloatRange::gain_skew_factor(-12.0, 0.0),
                 },
             )
             .with_smoother(SmoothingStyle::Logarithmic(50.0))
-            .with_value_to_string(formatters::v2s_compression_ratio(1))
-            .with_string_to_value(formatters::s2v_compression_ratio()),
+            .with_unit(" dBTP")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(1))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
 
-            attack: FloatParam::new(
-                "Attack",
-                20.0,
+            gain: gain_param("Gain"),
+
+            auto_makeup: BoolParam::new("Auto Makeup", false),
+
+            lookahead: FloatParam::new(
+                "Lookahead",
+                0.0,
                 FloatRange::Linear {
                     min: 0.0,
-                    max: 200.0,
+                    max: compressor::MAX_LOOKAHEAD * 1000.0,
                 },
             )
-            .with_smoother(SmoothingStyle::Linear(1.0))
-            .with_value_to_string(formatters::v2s_f32_rounded(0))
+            .with_smoother(SmoothingStyle::Linear(100.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
             .with_unit(" ms"),
 
-            release: FloatParam::new(
-                "Release",
-                140.0,
-                FloatRange::Linear {
-                    min: 0.0,
-                    max: 200.0,
+            mix: FloatParam::new("Mix", 1.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_unit("%")
+                .with_value_to_string(formatters::v2s_f32_percentage(0))
+                .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            ballistics: EnumParam::new("Ballistics", Ballistics::Linear),
+
+            architecture: EnumParam::new("Architecture", Architecture::Character),
+
+            oversampling: EnumParam::new("Oversampling", Oversampling::X1),
+
+            topology: EnumParam::new("Topology", Topology::FeedForward),
+
+            detector: EnumParam::new("Detector", DetectorMode::Peak),
+
+            rms_window: FloatParam::new(
+                "RMS Window",
+                20.0,
+                FloatRange::Skewed {
+                    min: 1.0,
+                    max: detector::MAX_RMS_WINDOW * 1000.0,
+                    factor: FloatRange::skew_factor(-1.0),
                 },
             )
-            .with_smoother(SmoothingStyle::Linear(1.0))
             .with_value_to_string(formatters::v2s_f32_rounded(0))
             .with_unit(" ms"),
 
-            steepness: FloatParam::new(
-                "Steepness",
-                8.0,
+            stereo_link: FloatParam::new(
+                "Stereo Link",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            link_mode: EnumParam::new("Link Mode", LinkMode::Max),
+
+            channel_mode: EnumParam::new("Channel Mode", ChannelMode::Stereo),
+
+            sidechain: EnumParam::new("Sidechain", SidechainSource::Internal),
+
+            sidechain_listen: BoolParam::new("Sidechain Listen", false),
+
+            sidechain_filter: EnumParam::new("Sidechain Filter", FilterType::Off),
+
+            sidechain_frequency: FloatParam::new(
+                "Sidechain Frequency",
+                100.0,
                 FloatRange::Skewed {
-                    min: 1.0,
-                    max: 300.0,
-                    factor: FloatRange::gain_skew_factor(1.0, 40.0),
+                    min: 20.0,
+                    max: 20000.0,
+                    factor: FloatRange::skew_factor(-2.0),
                 },
             )
-            .with_smoother(SmoothingStyle::Linear(1.0))
-            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
 
-            gain: FloatParam::new(
-                "Gain",
-                1.0,
+            sidechain_q: FloatParam::new(
+                "Sidechain Q",
+                std::f32::consts::FRAC_1_SQRT_2,
                 FloatRange::Skewed {
-                    min: util::db_to_gain(-6.0),
-                    max: util::db_to_gain(6.0),
-                    factor: FloatRange::gain_skew_factor(-6.0, 6.0),
+                    min: 0.1,
+                    max: 10.0,
+                    factor: FloatRange::skew_factor(-1.0),
                 },
             )
             .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            sidechain_gain: FloatParam::new(
+                "Sidechain Gain",
+                0.0,
+                FloatRange::Linear {
+                    min: -24.0,
+                    max: 24.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
             .with_unit(" dB")
-            .with_value_to_string(formatters::v2s_f32_gain_to_db(1))
-            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            side: DynamicsParams::new("Side"),
+
+            band_count: IntParam::new(
+                "Bands",
+                1,
+                IntRange::Linear {
+                    min: 1,
+                    max: MAX_BANDS as i32,
+                },
+            ),
+
+            crossover_low: crossover_param("Low Crossover", 150.0),
+            crossover_mid: crossover_param("Mid Crossover", 1500.0),
+            crossover_high: crossover_param("High Crossover", 6000.0),
+
+            bands: std::array::from_fn(|band| DynamicsParams::new(&format!("Band {}", band + 1))),
 
             #[cfg(feature = "detailed_debugging")]
             logger_length: FloatParam::new(
@@ -214,6 +934,96 @@ impl Default for CompressorParams {
     }
 }
 
+fn threshold_param(name: &str) -> FloatParam {
+    FloatParam::new(
+        name,
+        util::db_to_gain(-10.0),
+        FloatRange::Skewed {
+            min: util::db_to_gain(-30.0),
+            max: util::db_to_gain(0.0),
+            factor: FloatRange::gain_skew_factor(-30.0, 0.0),
+        },
+    )
+    .with_smoother(SmoothingStyle::Logarithmic(50.0))
+    .with_unit(" dB")
+    .with_value_to_string(formatters::v2s_f32_gain_to_db(1))
+    .with_string_to_value(formatters::s2v_f32_gain_to_db())
+}
+
+fn ratio_param(name: &str) -> FloatParam {
+    FloatParam::new(
+        name,
+        3.0,
+        FloatRange::Skewed {
+            min: 1.0,
+            max: 200.0,
+            factor: FloatRange::gain_skew_factor(1.0, 40.0),
+        },
+    )
+    .with_smoother(SmoothingStyle::Logarithmic(50.0))
+    .with_value_to_string(formatters::v2s_compression_ratio(1))
+    .with_string_to_value(formatters::s2v_compression_ratio())
+}
+
+fn attack_param(name: &str) -> FloatParam {
+    FloatParam::new(
+        name,
+        20.0,
+        FloatRange::Linear {
+            min: 0.0,
+            max: 200.0,
+        },
+    )
+    .with_smoother(SmoothingStyle::Linear(1.0))
+    .with_value_to_string(formatters::v2s_f32_rounded(0))
+    .with_unit(" ms")
+}
+
+fn release_param(name: &str) -> FloatParam {
+    FloatParam::new(
+        name,
+        140.0,
+        FloatRange::Linear {
+            min: 0.0,
+            max: 200.0,
+        },
+    )
+    .with_smoother(SmoothingStyle::Linear(1.0))
+    .with_value_to_string(formatters::v2s_f32_rounded(0))
+    .with_unit(" ms")
+}
+
+fn crossover_param(name: &str, default: f32) -> FloatParam {
+    FloatParam::new(
+        name,
+        default,
+        FloatRange::Skewed {
+            min: 20.0,
+            max: 20000.0,
+            factor: FloatRange::skew_factor(-2.0),
+        },
+    )
+    .with_smoother(SmoothingStyle::Logarithmic(50.0))
+    .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+    .with_string_to_value(formatters::s2v_f32_hz_then_khz())
+}
+
+fn gain_param(name: &str) -> FloatParam {
+    FloatParam::new(
+        name,
+        1.0,
+        FloatRange::Skewed {
+            min: util::db_to_gain(-6.0),
+            max: util::db_to_gain(6.0),
+            factor: FloatRange::gain_skew_factor(-6.0, 6.0),
+        },
+    )
+    .with_smoother(SmoothingStyle::Logarithmic(50.0))
+    .with_unit(" dB")
+    .with_value_to_string(formatters::v2s_f32_gain_to_db(1))
+    .with_string_to_value(formatters::s2v_f32_gain_to_db())
+}
+
 impl Plugin for Compressor {
     #[cfg(feature = "detailed_debugging")]
     const NAME: &'static str = "Compressor (debug)";
@@ -225,6 +1035,26 @@ impl Plugin for Compressor {
     const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
     const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(2),
+            main_output_channels: NonZeroU32::new(2),
+            aux_input_ports: &[new_nonzero_u32(2)],
+            names: PortNames {
+                aux_inputs: &["Sidechain"],
+                ..PortNames::const_default()
+            },
+            ..AudioIOLayout::const_default()
+        },
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(1),
+            main_output_channels: NonZeroU32::new(1),
+            aux_input_ports: &[new_nonzero_u32(1)],
+            names: PortNames {
+                aux_inputs: &["Sidechain"],
+                ..PortNames::const_default()
+            },
+            ..AudioIOLayout::const_default()
+        },
         AudioIOLayout {
             main_input_channels: NonZeroU32::new(2),
             main_output_channels: NonZeroU32::new(2),
@@ -247,26 +1077,83 @@ impl Plugin for Compressor {
         self.params.clone()
     }
 
+    fn filter_state(state: &mut PluginState) {
+        // Presets saved before the knee width existed only know the steepness, give them the
+        // width that sounds closest so switching the knee to width keeps the same knee.
+        if state.params.contains_key("knee_width") {
+            return;
+        }
+
+        if let (Some(ParamValue::F32(steepness)), Some(ParamValue::F32(threshold))) =
+            (state.params.get("steepness"), state.params.get("threshold"))
+        {
+            let knee_width = compressor::steepness_to_knee_width(*steepness, *threshold)
+                .clamp(0.0, KNEE_WIDTH_MAX_DB);
+            state
+                .params
+                .insert(String::from("knee_width"), ParamValue::F32(knee_width));
+        }
+    }
+
     fn editor(&mut self, _async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
-        editor::create(self.params.clone(), self.params.editor_state.clone())
+        editor::create(
+            self.params.clone(),
+            self.sample_rate.clone(),
+            self.params.editor_state.clone(),
+        )
     }
 
     fn initialize(
         &mut self,
-        _audio_io_layout: &AudioIOLayout,
-        _buffer_config: &BufferConfig,
-        _context: &mut impl InitContext<Self>,
+        audio_io_layout: &AudioIOLayout,
+        buffer_config: &BufferConfig,
+        context: &mut impl InitContext<Self>,
     ) -> bool {
+        self.sample_rate
+            .store(buffer_config.sample_rate, Ordering::Relaxed);
+
+        // Allocate all per-channel state here, `process` runs under `assert_process_allocs`.
+        let num_channels = audio_io_layout
+            .main_output_channels
+            .map(NonZeroU32::get)
+            .unwrap_or(0) as usize;
+
+        // Sized for the highest oversampling, so switching it later does not allocate.
+        let max_sample_rate = buffer_config.sample_rate * MAX_OVERSAMPLING as f32;
+        self.channels = (0..num_channels)
+            .map(|_| Channel {
+                oversampler: Oversampler::new(),
+                key_oversampler: Oversampler::new(),
+                crossover: Crossover::new(max_sample_rate),
+                key_crossover: Crossover::new(max_sample_rate),
+                algos: std::array::from_fn(|_| Algo::new(max_sample_rate)),
+            })
+            .collect();
+        self.set_oversampling(self.params.oversampling.value());
+
+        (self.latency, self.padding) = self.latency();
+        context.set_latency_samples(self.latency);
+
         true
     }
 
+    fn reset(&mut self) {
+        for channel in self.channels.iter_mut() {
+            channel.oversampler.reset();
+            channel.key_oversampler.reset();
+            channel.crossover.reset();
+            channel.key_crossover.reset();
+            channel.algos.iter_mut().for_each(Algo::reset);
+        }
+    }
+
     fn process(
         &mut self,
         buffer: &mut Buffer,
-        _aux: &mut AuxiliaryBuffers,
-        _context: &mut impl ProcessContext<Self>,
+        aux: &mut AuxiliaryBuffers,
+        context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
-        match self.process_buffer(buffer, _aux, _context) {
+        match self.process_buffer(buffer, aux, context) {
             Ok(_) => ProcessStatus::Normal,
             Err(err) => ProcessStatus::Error(err),
         }
@@ -0,0 +1,88 @@
+//! Gain computer of the brickwall limiter. The gain reaches the required reduction exactly when a
+//! peak leaves the lookahead delay, so it never overshoots the ceiling.
+
+use std::collections::VecDeque;
+
+use crate::moving_sum::MovingSum;
+
+pub struct Limiter {
+    /// Indices and required gains that can still be the minimum of the window, with increasing
+    /// gains from front to back.
+    minima: VecDeque<(u64, f32)>,
+    /// Samples processed so far.
+    index: u64,
+    /// Gain after the release, only ever above the held minimum while releasing.
+    released: f32,
+    /// Released gains of the window the minimum is held and averaged over.
+    gains: MovingSum,
+}
+
+impl Limiter {
+    /// Allocates room for windows of up to `max_window` samples.
+    pub fn new(max_window: usize) -> Self {
+        let max_window = max_window.max(1);
+
+        Self {
+            minima: VecDeque::with_capacity(max_window + 1),
+            index: 0,
+            released: 1.0,
+            gains: MovingSum::new(max_window, 1.0),
+        }
+    }
+
+    /// Makes room for windows of up to `max_window` samples and forgets all history. Only
+    /// allocates when the buffers are too short.
+    pub fn set_max_window(&mut self, max_window: usize) {
+        if max_window > self.gains.capacity() {
+            *self = Self::new(max_window);
+        } else {
+            self.reset();
+        }
+    }
+
+    /// Takes the gain the current sample needs to stay below the ceiling and returns the gain for
+    /// the sample that was taken `window - 1` samples ago. `release_coefficient` is the one-pole
+    /// coefficient the gain recovers with.
+    pub fn process(&mut self, required: f32, window: usize, release_coefficient: f32) -> f32 {
+        self.gains.set_window(window);
+        let window = self.gains.window();
+
+        while self
+            .minima
+            .back()
+            .is_some_and(|&(_, gain)| gain >= required)
+        {
+            self.minima.pop_back();
+        }
+        self.minima.push_back((self.index, required));
+        while self
+            .minima
+            .front()
+            .is_some_and(|&(index, _)| index + window as u64 <= self.index)
+        {
+            self.minima.pop_front();
+        }
+        self.index += 1;
+
+        // Held at the lowest gain any sample in the window needs, so the average below can
+        // never be above what the oldest sample needs.
+        let held = self.minima.front().map_or(1.0, |&(_, gain)| gain);
+
+        self.released = if held < self.released {
+            held
+        } else {
+            held + release_coefficient * (self.released - held)
+        };
+
+        self.gains.push(self.released);
+
+        self.gains.mean() as f32
+    }
+
+    pub fn reset(&mut self) {
+        self.minima.clear();
+        self.index = 0;
+        self.released = 1.0;
+        self.gains.reset();
+    }
+}
@@ -0,0 +1,194 @@
+//! Analog models that change how [`crate::compressor::Algo`] behaves as a whole.
+
+use std::f32::consts::PI;
+
+use nih_plug::prelude::*;
+
+use crate::compressor::{flush, static_curve, Prepared, RawParameters, Topology};
+
+/// Factor the attack is scaled with by the FET model, mapping the full attack range onto the
+/// sub-millisecond attacks of FET compressors.
+const FET_ATTACK_SCALE: f32 = 0.004;
+/// Drive of the FET saturation at an infinite ratio.
+const FET_DRIVE: f32 = 0.5;
+/// Bias of the FET saturation, makes the curve asymmetric so even harmonics appear.
+const FET_BIAS: f32 = 0.2;
+/// Gain reduction in dB at which the FET adds all of its harmonics.
+const FET_FULL_DB: f32 = 12.0;
+/// Cutoff in Hz of the DC blocker that removes the offset the bias adds.
+const FET_DC_BLOCK_FREQUENCY: f32 = 10.0;
+
+/// Gain reduction in dB that fully lights the opto cell.
+const OPTO_FULL_DB: f32 = 12.0;
+/// Time constant in seconds the opto cell takes to charge up during compression.
+const OPTO_CHARGE: f32 = 1.0;
+/// Time constant in seconds the opto cell takes to forget it was lit.
+const OPTO_MEMORY: f32 = 5.0;
+/// How many times longer the release gets with a fully lit cell.
+const OPTO_RELEASE_SPREAD: f32 = 10.0;
+
+/// Behavior of a compressor circuit around the shared envelope and gain computer.
+pub trait Model {
+    /// Turns the parameters that were set into the ones this model runs with. Called for the
+    /// detection and the compression of every sample, the result has to stay the same for the
+    /// same parameters so the values derived from them can be reused.
+    fn parameters(&self, p: RawParameters) -> RawParameters {
+        p
+    }
+
+    /// Factors the attack and the release time are scaled with on the next sample, for models
+    /// whose timing depends on what they compressed.
+    fn time_scale(&self) -> (f32, f32) {
+        (1.0, 1.0)
+    }
+
+    /// Takes the envelope after it moved, so program-dependent models can remember the signal.
+    fn observe(&mut self, _envelope: f32, _prepared: &Prepared, _sample_rate: f32) {}
+
+    /// Adds the coloration of the circuit to the compressed sample.
+    fn color(&mut self, sample: f32, _p: &RawParameters) -> f32 {
+        sample
+    }
+
+    /// Forgets all history.
+    fn reset(&mut self) {}
+}
+
+/// Model to run the compressor with.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AnalogModel {
+    /// Clean and fast, the compressor as set.
+    #[id = "vca"]
+    #[name = "VCA"]
+    Vca,
+    /// Feedback with a very fast attack and harmonics that grow with the ratio and the gain
+    /// reduction.
+    #[id = "fet"]
+    #[name = "FET"]
+    Fet,
+    /// Light-dependent resistor: the attack speeds up on big overs and the release slows down
+    /// the longer the compressor worked.
+    #[id = "opto"]
+    Opto,
+}
+
+pub struct Vca;
+
+impl Model for Vca {}
+
+pub struct Fet {
+    /// Gain reduction in dB on the last sample, the harmonics grow with it.
+    gain_reduction: f32,
+    /// Sample rate the DC blocker coefficient was computed for.
+    sample_rate: f32,
+    dc_coefficient: f32,
+    /// Last input and output of the DC blocker.
+    dc_input: f32,
+    dc_output: f32,
+}
+
+impl Fet {
+    pub fn new() -> Self {
+        Self {
+            gain_reduction: 0.0,
+            sample_rate: 0.0,
+            dc_coefficient: 0.0,
+            dc_input: 0.0,
+            dc_output: 0.0,
+        }
+    }
+}
+
+impl Model for Fet {
+    fn parameters(&self, p: RawParameters) -> RawParameters {
+        RawParameters {
+            topology: Topology::Feedback,
+            attack: p.attack * FET_ATTACK_SCALE,
+            ..p
+        }
+    }
+
+    fn observe(&mut self, envelope: f32, prepared: &Prepared, sample_rate: f32) {
+        self.gain_reduction = gain_reduction_db(envelope, prepared);
+
+        if sample_rate != self.sample_rate {
+            self.dc_coefficient = (-2.0 * PI * FET_DC_BLOCK_FREQUENCY / sample_rate).exp();
+            self.sample_rate = sample_rate;
+        }
+    }
+
+    fn color(&mut self, sample: f32, p: &RawParameters) -> f32 {
+        let drive = 1.0 + FET_DRIVE * (1.0 - 1.0 / p.ratio.max(1.0));
+        let bias = FET_BIAS.tanh();
+        // Keeps the small signal gain at one, so only the harmonics are added.
+        let slope = drive * (1.0 - bias * bias);
+        let harmonics = ((sample * drive + FET_BIAS).tanh() - bias) / slope - sample;
+
+        // The bias makes the harmonics lopsided, which would add an offset.
+        self.dc_output = flush(harmonics - self.dc_input + self.dc_coefficient * self.dc_output);
+        self.dc_input = harmonics;
+
+        let amount = (self.gain_reduction / FET_FULL_DB).min(1.0);
+        sample + amount * self.dc_output
+    }
+
+    fn reset(&mut self) {
+        self.gain_reduction = 0.0;
+        self.dc_input = 0.0;
+        self.dc_output = 0.0;
+    }
+}
+
+pub struct Opto {
+    /// Gain reduction in dB on the last sample.
+    gain_reduction: f32,
+    /// How lit the cell is in [0, 1], charged by gain reduction and slowly forgotten.
+    light: f32,
+}
+
+impl Opto {
+    pub fn new() -> Self {
+        Self {
+            gain_reduction: 0.0,
+            light: 0.0,
+        }
+    }
+}
+
+impl Model for Opto {
+    fn time_scale(&self) -> (f32, f32) {
+        (
+            1.0 / (1.0 + self.gain_reduction / OPTO_FULL_DB),
+            1.0 + OPTO_RELEASE_SPREAD * self.light,
+        )
+    }
+
+    fn observe(&mut self, envelope: f32, prepared: &Prepared, sample_rate: f32) {
+        self.gain_reduction = gain_reduction_db(envelope, prepared);
+
+        let target = (self.gain_reduction / OPTO_FULL_DB).min(1.0);
+        let time = if target > self.light {
+            OPTO_CHARGE
+        } else {
+            OPTO_MEMORY
+        };
+        let coefficient = (-1.0 / (time * sample_rate)).exp();
+
+        self.light = flush(target + coefficient * (self.light - target));
+    }
+
+    fn reset(&mut self) {
+        self.gain_reduction = 0.0;
+        self.light = 0.0;
+    }
+}
+
+/// Gain reduction in dB the static curve applies to a steady signal at `envelope`.
+fn gain_reduction_db(envelope: f32, prepared: &Prepared) -> f32 {
+    if envelope > 0.0 {
+        let compressed = static_curve(envelope, prepared);
+        (util::gain_to_db(envelope) - util::gain_to_db(compressed)).max(0.0)
+    } else {
+        0.0
+    }
+}
@@ -0,0 +1,112 @@
+//! Running sum over the most recent values, for the moving averages of the RMS detector and the
+//! limiter.
+
+pub struct MovingSum {
+    /// Values pushed so far, written as a ring buffer.
+    values: Vec<f32>,
+    position: usize,
+    /// Amount of values in the sum.
+    window: usize,
+    /// Sum of the last `window` values. Kept in f64 so the running sum does not drift.
+    sum: f64,
+    /// Value the history starts out with before anything is pushed.
+    initial: f32,
+}
+
+impl MovingSum {
+    /// Allocates room for windows of up to `capacity` values and fills the history with `initial`.
+    pub fn new(capacity: usize, initial: f32) -> Self {
+        Self {
+            values: vec![initial; capacity.max(1)],
+            position: 0,
+            window: 1,
+            sum: initial as f64,
+            initial,
+        }
+    }
+
+    /// Longest window that fits in the history.
+    pub fn capacity(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Makes room for windows of up to `capacity` values and forgets all history. Only allocates
+    /// when the buffer is too short.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        if capacity > self.values.len() {
+            self.values = vec![self.initial; capacity];
+        }
+
+        self.window = 1;
+        self.reset();
+    }
+
+    /// Sums the last `window` values from now on, clamped to the capacity. The sum is recomputed
+    /// from the history, so it is right straight away.
+    pub fn set_window(&mut self, window: usize) {
+        let window = window.clamp(1, self.values.len());
+
+        if window == self.window {
+            return;
+        }
+
+        self.window = window;
+        self.sum = (1..=window)
+            .map(|age| self.values[self.index_of_age(age)] as f64)
+            .sum();
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    /// Adds `value` to the sum and drops the value that falls out of the window.
+    pub fn push(&mut self, value: f32) {
+        let leaving = self.values[self.index_of_age(self.window)];
+
+        self.values[self.position] = value;
+        self.position = (self.position + 1) % self.values.len();
+        self.sum += value as f64 - leaving as f64;
+    }
+
+    /// Mean of the last `window` values.
+    pub fn mean(&self) -> f64 {
+        self.sum / self.window as f64
+    }
+
+    /// Index of the value that was written `age` values ago.
+    fn index_of_age(&self, age: usize) -> usize {
+        (self.position + self.values.len() - age) % self.values.len()
+    }
+
+    /// Forgets all history, as if only `initial` was pushed so far.
+    pub fn reset(&mut self) {
+        self.values.fill(self.initial);
+        self.position = 0;
+        self.sum = self.window as f64 * self.initial as f64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mean_covers_the_window() {
+        let mut sum = MovingSum::new(8, 1.0);
+        sum.set_window(4);
+        assert_eq!(sum.mean(), 1.0);
+
+        for value in [2.0, 4.0, 6.0, 8.0, 10.0] {
+            sum.push(value);
+        }
+        assert_eq!(sum.mean(), 7.0);
+
+        // A longer window is summed from the history straight away.
+        sum.set_window(6);
+        assert_eq!(sum.mean(), 31.0 / 6.0);
+
+        sum.reset();
+        assert_eq!(sum.mean(), 1.0);
+    }
+}
@@ -0,0 +1,371 @@
+//! Polyphase half-band filters that run the compression at a multiple of the sample rate, so the
+//! harmonics of the nonlinear gain stage do not alias back into the audible range.
+
+use std::f32::consts::PI;
+
+use nih_plug::prelude::*;
+
+/// Highest oversampling factor, buffers are sized for this.
+pub const MAX_OVERSAMPLING: usize = 8;
+/// Half-band stages needed for [`MAX_OVERSAMPLING`].
+const MAX_STAGES: usize = 3;
+
+/// Length of the half-band filter. Every other tap is zero apart from the center one, so only
+/// one polyphase branch needs to be convolved and the other is a plain delay.
+const HALF_BAND_LENGTH: usize = 63;
+/// Taps of the branch that is convolved.
+const BRANCH_TAPS: usize = HALF_BAND_LENGTH / 2 + 1;
+/// Index of the center tap, which is also the delay of one filter at the higher rate.
+const CENTER: usize = HALF_BAND_LENGTH / 2;
+
+/// Rate the compression runs at relative to the host sample rate.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Oversampling {
+    #[id = "1x"]
+    #[name = "1x"]
+    X1,
+    #[id = "2x"]
+    #[name = "2x"]
+    X2,
+    #[id = "4x"]
+    #[name = "4x"]
+    X4,
+    #[id = "8x"]
+    #[name = "8x"]
+    X8,
+}
+
+impl Oversampling {
+    pub fn factor(self) -> usize {
+        1 << self.stages()
+    }
+
+    fn stages(self) -> usize {
+        match self {
+            Oversampling::X1 => 0,
+            Oversampling::X2 => 1,
+            Oversampling::X4 => 2,
+            Oversampling::X8 => 3,
+        }
+    }
+
+    /// Delay of upsampling and downsampling again, in samples at the host sample rate.
+    pub fn latency(self) -> f32 {
+        // Every stage delays by `CENTER` samples up and down at twice the rate of the stage
+        // before it.
+        (1..=self.stages())
+            .map(|stage| 2.0 * CENTER as f32 / (1 << stage) as f32)
+            .sum()
+    }
+
+    /// Latency in whole host samples when the compression delays the audio by `delay` samples at
+    /// the oversampled rate, and the samples at that rate the audio has to be delayed by on top
+    /// to match it. Reporting a fractional latency rounded would leave the host's delay
+    /// compensation off by up to half a sample.
+    pub fn whole_latency(self, delay: f32) -> (u32, f32) {
+        let factor = self.factor() as f32;
+        let exact = self.latency() + delay / factor;
+        let latency = exact.ceil();
+
+        (latency as u32, (latency - exact) * factor)
+    }
+}
+
+/// Taps of the convolved branch, the even taps of a Blackman windowed sinc with its cutoff at a
+/// quarter of the higher rate.
+fn branch_taps() -> [f32; BRANCH_TAPS] {
+    let mut taps = [0.0; BRANCH_TAPS];
+
+    for (index, tap) in taps.iter_mut().enumerate() {
+        let n = 2 * index;
+        let t = (n as f32 - CENTER as f32) / 2.0;
+        let sinc = (PI * t).sin() / (PI * t);
+        let phase = 2.0 * PI * n as f32 / (HALF_BAND_LENGTH - 1) as f32;
+        let window = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+
+        *tap = 0.5 * sinc * window;
+    }
+
+    // The branch passes half of the signal at DC, the center tap the other half.
+    let sum: f32 = taps.iter().sum();
+    taps.iter_mut().for_each(|tap| *tap *= 0.5 / sum);
+
+    taps
+}
+
+/// Doubles the sample rate of a signal.
+struct Upsampler {
+    taps: [f32; BRANCH_TAPS],
+    /// Most recent input samples, `history[position]` is the newest.
+    history: [f32; BRANCH_TAPS],
+    position: usize,
+}
+
+impl Upsampler {
+    fn new() -> Self {
+        Self {
+            taps: branch_taps(),
+            history: [0.0; BRANCH_TAPS],
+            position: 0,
+        }
+    }
+
+    /// Takes one sample and returns the two samples at the double rate.
+    fn process(&mut self, sample: f32) -> [f32; 2] {
+        self.position = (self.position + 1) % BRANCH_TAPS;
+        self.history[self.position] = sample;
+
+        // Zero stuffing halves the level, which the factor two makes up for.
+        let convolved = 2.0 * convolve(&self.taps, &self.history, self.position);
+        let delayed = self.history[(self.position + BRANCH_TAPS - CENTER / 2) % BRANCH_TAPS];
+
+        [convolved, delayed]
+    }
+
+    fn reset(&mut self) {
+        self.history = [0.0; BRANCH_TAPS];
+        self.position = 0;
+    }
+}
+
+/// Halves the sample rate of a signal.
+struct Downsampler {
+    taps: [f32; BRANCH_TAPS],
+    /// Most recent even input samples, `even[position]` is the newest.
+    even: [f32; BRANCH_TAPS],
+    /// Most recent odd input samples, only read by the center tap.
+    odd: [f32; BRANCH_TAPS],
+    position: usize,
+}
+
+impl Downsampler {
+    fn new() -> Self {
+        Self {
+            taps: branch_taps(),
+            even: [0.0; BRANCH_TAPS],
+            odd: [0.0; BRANCH_TAPS],
+            position: 0,
+        }
+    }
+
+    /// Takes two samples at the double rate and returns one sample.
+    fn process(&mut self, [even, odd]: [f32; 2]) -> f32 {
+        self.position = (self.position + 1) % BRANCH_TAPS;
+        self.even[self.position] = even;
+        self.odd[self.position] = odd;
+
+        let delayed = self.odd[(self.position + BRANCH_TAPS - CENTER / 2 - 1) % BRANCH_TAPS];
+
+        convolve(&self.taps, &self.even, self.position) + 0.5 * delayed
+    }
+
+    fn reset(&mut self) {
+        self.even = [0.0; BRANCH_TAPS];
+        self.odd = [0.0; BRANCH_TAPS];
+        self.position = 0;
+    }
+}
+
+/// Convolves `taps` with the ring buffer `history` whose newest sample is at `position`.
+fn convolve(taps: &[f32; BRANCH_TAPS], history: &[f32; BRANCH_TAPS], position: usize) -> f32 {
+    taps.iter()
+        .enumerate()
+        .map(|(age, tap)| tap * history[(position + BRANCH_TAPS - age) % BRANCH_TAPS])
+        .sum()
+}
+
+/// Oversamples one channel through a cascade of half-band stages.
+pub struct Oversampler {
+    /// `up[stage]` and `down[stage]` run between `2^stage` and `2^(stage + 1)` times the rate.
+    up: [Upsampler; MAX_STAGES],
+    down: [Downsampler; MAX_STAGES],
+}
+
+impl Oversampler {
+    pub fn new() -> Self {
+        Self {
+            up: [Upsampler::new(), Upsampler::new(), Upsampler::new()],
+            down: [Downsampler::new(), Downsampler::new(), Downsampler::new()],
+        }
+    }
+
+    /// Returns `sample` at `oversampling` times the rate. Only the first
+    /// [`Oversampling::factor`] samples are used.
+    pub fn upsample(&mut self, sample: f32, oversampling: Oversampling) -> [f32; MAX_OVERSAMPLING] {
+        let mut samples = [0.0; MAX_OVERSAMPLING];
+        samples[0] = sample;
+
+        let mut length = 1;
+        for stage in self.up.iter_mut().take(oversampling.stages()) {
+            let input = samples;
+            for (pair, sample) in samples.chunks_exact_mut(2).zip(&input[..length]) {
+                pair.copy_from_slice(&stage.process(*sample));
+            }
+            length *= 2;
+        }
+
+        samples
+    }
+
+    /// Brings the first [`Oversampling::factor`] of `samples` back to the host rate.
+    pub fn downsample(
+        &mut self,
+        mut samples: [f32; MAX_OVERSAMPLING],
+        oversampling: Oversampling,
+    ) -> f32 {
+        let mut length = oversampling.factor();
+        for stage in self.down.iter_mut().take(oversampling.stages()).rev() {
+            let input = samples;
+            for (sample, pair) in samples.iter_mut().zip(input[..length].chunks_exact(2)) {
+                *sample = stage.process([pair[0], pair[1]]);
+            }
+            length /= 2;
+        }
+
+        samples[0]
+    }
+
+    pub fn reset(&mut self) {
+        self.up.iter_mut().for_each(Upsampler::reset);
+        self.down.iter_mut().for_each(Downsampler::reset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::tests::parameters;
+    use crate::compressor::{self, Algo, Architecture, Knee, RawParameters};
+    use crate::detector::DetectorMode;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    /// Amplitude and phase of the component at `frequency` Hz in one second of `samples`.
+    fn component(samples: &[f32], frequency: f32) -> (f32, f32) {
+        let (mut re, mut im) = (0.0f64, 0.0f64);
+        for (n, sample) in samples.iter().enumerate() {
+            let phase =
+                2.0 * std::f64::consts::PI * frequency as f64 * n as f64 / SAMPLE_RATE as f64;
+            re += *sample as f64 * phase.cos();
+            im -= *sample as f64 * phase.sin();
+        }
+        let length = samples.len() as f64;
+
+        ((2.0 * re.hypot(im) / length) as f32, im.atan2(re) as f32)
+    }
+
+    /// Runs one second of a sine at `frequency` Hz through the oversampler and `process` at the
+    /// higher rate after a second to settle, and returns the output at the host rate.
+    fn oversample(
+        oversampling: Oversampling,
+        frequency: f32,
+        mut process: impl FnMut(&mut f32),
+    ) -> Vec<f32> {
+        let mut oversampler = Oversampler::new();
+        let factor = oversampling.factor();
+        let output: Vec<f32> = (0..2 * SAMPLE_RATE as usize)
+            .map(|n| {
+                let phase = 2.0 * PI * frequency * (n % SAMPLE_RATE as usize) as f32 / SAMPLE_RATE;
+                let mut samples = oversampler.upsample(0.9 * phase.sin(), oversampling);
+                samples[..factor].iter_mut().for_each(&mut process);
+                oversampler.downsample(samples, oversampling)
+            })
+            .collect();
+
+        output[SAMPLE_RATE as usize..].to_vec()
+    }
+
+    #[test]
+    fn oversampling_reduces_aliasing_of_the_character_architecture() {
+        // Every harmonic of this sine is above the host Nyquist frequency, so at the host rate
+        // everything but the fundamental is aliasing. Oversampled, the downsampler removes the
+        // harmonics and only what aliased at the higher rate is left.
+        let frequency = 13000.0;
+        let p = RawParameters {
+            architecture: Architecture::Character,
+            knee: Knee::Steepness,
+            ratio: 20.0,
+            release: 0.5,
+            ..parameters()
+        };
+
+        // Level of everything but the fundamental relative to the fundamental in dB.
+        let alias_db = |oversampling: Oversampling| {
+            let mut algo = Algo::new(SAMPLE_RATE * oversampling.factor() as f32);
+            let output = oversample(oversampling, frequency, |sample| {
+                algo.process_samples(sample, p, None).unwrap()
+            });
+
+            let (fundamental, _) = component(&output, frequency);
+            let energy: f32 = output.iter().map(|sample| sample * sample).sum();
+            let residual = 2.0 * energy / output.len() as f32 - fundamental * fundamental;
+
+            util::gain_to_db(residual.max(0.0).sqrt() / fundamental)
+        };
+
+        // Every doubling of the rate leaves fewer harmonics that alias.
+        let mut previous = alias_db(Oversampling::X1);
+        for oversampling in [Oversampling::X2, Oversampling::X4, Oversampling::X8] {
+            let oversampled = alias_db(oversampling);
+            assert!(
+                oversampled < previous - 6.0,
+                "{oversampling:?}: {oversampled} dB against {previous} dB"
+            );
+            previous = oversampled;
+        }
+    }
+
+    #[test]
+    fn latency_is_the_measured_delay() {
+        let frequency = 100.0;
+        let (_, input_phase) =
+            component(&oversample(Oversampling::X1, frequency, |_| ()), frequency);
+        // Samples `output` lags the input by.
+        let delay = |output: &[f32]| {
+            let (amplitude, phase) = component(output, frequency);
+            assert!((amplitude - 0.9).abs() < 0.001, "{amplitude}");
+
+            (input_phase - phase).rem_euclid(2.0 * PI) / (2.0 * PI * frequency) * SAMPLE_RATE
+        };
+
+        for oversampling in [Oversampling::X2, Oversampling::X4, Oversampling::X8] {
+            let measured = delay(&oversample(oversampling, frequency, |_| ()));
+            assert!(
+                (measured - oversampling.latency()).abs() < 0.01,
+                "{oversampling:?}: {measured} samples against {}",
+                oversampling.latency()
+            );
+        }
+
+        // With the padding, the lookahead and the true-peak detector on top of the filters add up
+        // to the whole number of samples reported to the host.
+        for oversampling in [
+            Oversampling::X1,
+            Oversampling::X2,
+            Oversampling::X4,
+            Oversampling::X8,
+        ] {
+            let sample_rate = SAMPLE_RATE * oversampling.factor() as f32;
+            let p = RawParameters {
+                threshold: 1.0,
+                detector: DetectorMode::TruePeak,
+                lookahead: 0.0013,
+                ..parameters()
+            };
+            let (latency, padding) = oversampling.whole_latency(compressor::delay(&p, sample_rate));
+            let p = RawParameters {
+                lookahead: p.lookahead + padding / sample_rate,
+                ..p
+            };
+
+            let mut algo = Algo::new(sample_rate);
+            let measured = delay(&oversample(oversampling, frequency, |sample| {
+                algo.process_samples(sample, p, None).unwrap()
+            }));
+            assert!(
+                (measured - latency as f32).abs() < 0.01,
+                "{oversampling:?}: {measured} samples against {latency}"
+            );
+        }
+    }
+}